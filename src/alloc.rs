@@ -0,0 +1,202 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// set by the first allocation that goes through TrackingAllocator
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// bytes that are currently allocated on the heap
+static HEAP_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_STATS: Cell<AllocStats> = const { Cell::new(AllocStats { allocs: 0, bytes: 0 }) };
+    // set while gbench records the events of the thread
+    static PAUSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Allocation counters of a thread
+///
+/// The counters only grow, so the allocations made during
+/// a piece of code are the difference between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocStats {
+    /// Number of allocations
+    pub allocs: u64,
+    /// Number of allocated bytes
+    pub bytes: u64,
+}
+
+impl AllocStats {
+    /// Returns the counters of the current thread
+    ///
+    /// Returns `None` if [TrackingAllocator] is not the global allocator.
+    ///
+    /// [TrackingAllocator]: struct.TrackingAllocator.html
+    pub fn current() -> Option<AllocStats> {
+        if INSTALLED.load(Ordering::Relaxed) {
+            THREAD_STATS.try_with(|stats| stats.get()).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of bytes that are currently allocated
+    ///
+    /// Returns `None` if [TrackingAllocator] is not the global allocator.
+    ///
+    /// [TrackingAllocator]: struct.TrackingAllocator.html
+    pub fn heap_bytes() -> Option<usize> {
+        if INSTALLED.load(Ordering::Relaxed) {
+            Some(HEAP_BYTES.load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn since(self, start: AllocStats) -> AllocStats {
+        AllocStats {
+            allocs: self.allocs - start.allocs,
+            bytes: self.bytes - start.bytes,
+        }
+    }
+}
+
+// Stops counting the allocations of the thread until it is dropped,
+// so the allocations made by gbench are not charged to the scopes
+pub(crate) struct Paused(bool);
+
+pub(crate) fn pause() -> Paused {
+    Paused(PAUSED.try_with(|paused| paused.replace(true)).unwrap_or(true))
+}
+
+impl Drop for Paused {
+    fn drop(&mut self) {
+        let _ = PAUSED.try_with(|paused| paused.set(self.0));
+    }
+}
+
+fn record_alloc(size: usize) {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        INSTALLED.store(true, Ordering::Relaxed);
+    }
+
+    HEAP_BYTES.fetch_add(size, Ordering::Relaxed);
+
+    if PAUSED.try_with(Cell::get).unwrap_or(true) {
+        return;
+    }
+
+    let _ = THREAD_STATS.try_with(|stats| {
+        let AllocStats { allocs, bytes } = stats.get();
+        stats.set(AllocStats {
+            allocs: allocs + 1,
+            bytes: bytes + size as u64,
+        });
+    });
+}
+
+fn record_dealloc(size: usize) {
+    HEAP_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Global allocator that tracks the allocations
+///
+/// TrackingAllocator wraps another allocator and counts the
+/// allocations and allocated bytes of every thread. When it
+/// is installed every [TimeScope] records the allocations
+/// made while it was open in its args and
+/// [Instantiator::sample_heap] can be used to
+/// collect the heap size over time.
+///
+/// [TimeScope]: struct.TimeScope.html
+/// [Instantiator::sample_heap]: struct.Instantiator.html#method.sample_heap
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, ChromeTracing, TrackingAllocator};
+/// use std::alloc::System;
+/// use std::time::Duration;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TrackingAllocator<System> = TrackingAllocator(System);
+///
+/// fn main() {
+///     instantiate!(ginst | ChromeTracing("target/bench"));
+///     // Counter "Heap" will contain the allocated bytes
+///     ginst.sample_heap(Duration::from_millis(1));
+///     {
+///         // This scope will have "allocs" and "alloc bytes" args
+///         scope!(sc | "Scope");
+///         let _v = vec![0u8; 1024];
+///     }
+/// }
+/// ```
+///
+/// The allocations made by gbench, such as the events of the nested
+/// scopes, are not counted
+///
+/// ```rust
+/// use gbench::{instantiate, scope, Arg, BenchData, TrackingAllocator, Writer};
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TrackingAllocator<System> = TrackingAllocator(System);
+///
+/// // Checks that "Outer" did not allocate
+/// struct AllocCheck;
+///
+/// impl Writer for AllocCheck {
+///     fn end(&self, data: &[BenchData]) {
+///         let allocs = data.iter().find_map(|data| match data {
+///             BenchData::Bench { name, args, .. } if name == "Outer" => {
+///                 args.iter().find(|arg| arg.0 == "allocs").map(|arg| arg.1.clone())
+///             }
+///             _ => None,
+///         });
+///         assert_eq!(allocs, Some(Arg::Int(0)));
+///     }
+/// }
+///
+/// fn main() {
+///     instantiate!(AllocCheck);
+///
+///     scope!(outer | "Outer");
+///     for i in 0..100 {
+///         scope!(inner | "Inner {}", i);
+///     }
+/// }
+/// ```
+pub struct TrackingAllocator<A: GlobalAlloc>(pub A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout);
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.0.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record_dealloc(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
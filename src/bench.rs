@@ -1,70 +1,301 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
 use std::mem;
-use std::time::Instant;
+use std::thread;
+use std::time::Duration;
 
-use crate::global::{begin, begin_time, end, gen_id, get_id, queue_mutex, BenchData};
-use crate::writer::Writer;
+use crate::alloc::{self, AllocStats};
+use crate::clock::Clock;
+use crate::collector::{Collector, ThreadScopes};
+use crate::filter;
+use crate::global::{current, get_id, next_span, remove_default, set_default, Arg, OpenScope};
+use crate::live::Endpoint;
+use crate::process;
+use crate::queue::Window;
+use crate::sampler::Sampler;
+use crate::writer::{StreamWriter, Writer};
 
-fn ts_of(instant: Instant) -> f32 {
-    (instant.duration_since(begin_time()).as_nanos() as f32) / 1000.0
+pub fn _log(log: String, cat: String) {
+    log_with_args(log, cat, Vec::new());
 }
 
-fn enqueue(data: BenchData) {
-    let mut lock = queue_mutex();
-    lock.push(data);
+pub(crate) fn log_with_args(log: String, cat: String, args: Vec<(String, Arg)>) {
+    if let Some(collector) = current() {
+        collector.log(log, cat, args);
+    }
 }
 
-pub fn _log(log: String) {
-    let ts = ts_of(Instant::now());
-    let tid = get_id();
-
-    enqueue(BenchData::Log { log, ts, tid });
+thread_local! {
+    // collector keys and span ids of the scopes
+    // of the thread that are not ended
+    static SPAN_STACK: RefCell<Vec<(u64, u64)>> = const { RefCell::new(Vec::new()) };
 }
 
-fn bench(name: String, ts: f32) {
-    let dur = ts_of(Instant::now()) - ts;
-    let tid = get_id();
-
-    enqueue(BenchData::Bench { name, ts, dur, tid });
+// adds a counter without checking the filter
+pub fn _count(name: String, cat: String, data: Vec<(String, f32)>) {
+    if let Some(collector) = current() {
+        collector.count(name, cat, data);
+    }
 }
 
-pub fn _count(name: String, data: Vec<(String, f32)>) {
-    let ts = ts_of(Instant::now());
-    let tid = get_id();
-
-    enqueue(BenchData::Count {
-        name,
-        data,
-        ts,
-        tid,
-    });
+// adds a counter if it is not filtered out
+pub(crate) fn count(name: String, cat: String, data: Vec<(String, f32)>) {
+    if filter::enabled(&name, &cat) {
+        _count(name, cat, data);
+    }
 }
 
 /// Starts a benchmarking scope on creation and ends it on drop
 ///
-/// TimeScope saves the Instant it was created. When dropped it
+/// TimeScope saves the moment it was created. When dropped it
 /// saves the benchmarking results to the file.
 ///
+/// If [TrackingAllocator] is the global allocator the allocations
+/// made while TimeScope was alive are saved in "allocs" and
+/// "alloc bytes" args. If TimeScope is dropped while its thread
+/// panics it is marked with "unwound" arg.
+///
+/// If TimeScope is still alive when the global variables are
+/// deinstantiated it is ended at that moment and marked with
+/// "incomplete" arg.
+///
+/// TimeScope can not be sent to another thread, as the scopes are
+/// nested in the scopes of the thread they are started on.
+///
+/// ```rust,compile_fail
+/// use gbench::TimeScope;
+///
+/// let scope = TimeScope::new("Scope");
+/// std::thread::spawn(move || drop(scope));
+/// ```
+///
 /// Using [scope!] macro instead of this struct is recommened.
 ///
 /// [scope!]: macro.scope.html
+/// [TrackingAllocator]: struct.TrackingAllocator.html
 pub struct TimeScope {
-    start: f32,
-    name: String,
+    // None if the scope is not collected
+    span: Option<(Collector, u64, ThreadScopes)>,
+    allocs: Option<AllocStats>,
+    args: Vec<(String, Arg)>,
+    // the scope is ended on the thread it is started on
+    _thread: PhantomData<*const ()>,
+}
+
+// name of a scope, which is not allocated if it has no arguments
+fn scope_name(name: fmt::Arguments) -> Cow<'static, str> {
+    match name.as_str() {
+        Some(name) => Cow::Borrowed(name),
+        None => Cow::Owned(name.to_string()),
+    }
+}
+
+// starts a scope without checking the filter
+#[doc(hidden)]
+pub fn _scope(name: fmt::Arguments, cat: &'static str) -> TimeScope {
+    let _paused = alloc::pause();
+    TimeScope::start(scope_name(name), Cow::Borrowed(cat))
+}
+
+// starts a scope that is collected in 1 of `weight` executions
+#[doc(hidden)]
+pub fn _sampled_scope(name: fmt::Arguments, cat: &'static str, weight: u32) -> TimeScope {
+    let _paused = alloc::pause();
+    let mut scope = TimeScope::start(scope_name(name), Cow::Borrowed(cat));
+    scope.arg(String::from("weight"), Arg::Int(weight as i64));
+    scope
 }
 
 impl TimeScope {
-    pub fn new(name: String) -> TimeScope {
-        gen_id();
+    /// Starts the scope
+    ///
+    /// The scope is not collected if its name is filtered
+    /// out by the [Filter].
+    ///
+    /// [Filter]: struct.Filter.html
+    pub fn new(name: impl Into<Cow<'static, str>>) -> TimeScope {
+        TimeScope::with_category(name, "function")
+    }
+
+    /// Starts the scope with a category
+    ///
+    /// The scope is not collected if its name or category is
+    /// filtered out by the [Filter].
+    ///
+    /// [Filter]: struct.Filter.html
+    pub fn with_category(
+        name: impl Into<Cow<'static, str>>,
+        cat: impl Into<Cow<'static, str>>,
+    ) -> TimeScope {
+        let (name, cat) = (name.into(), cat.into());
+        if filter::enabled(&name, &cat) {
+            TimeScope::start(name, cat)
+        } else {
+            TimeScope::disabled()
+        }
+    }
+
+    /// Constructs a scope that is not collected
+    pub fn disabled() -> TimeScope {
         TimeScope {
-            start: ts_of(Instant::now()),
+            span: None,
+            allocs: None,
+            args: Vec::new(),
+            _thread: PhantomData,
+        }
+    }
+
+    // starts the scope in the current collector
+    fn start(name: Cow<'static, str>, cat: Cow<'static, str>) -> TimeScope {
+        match current() {
+            Some(collector) => TimeScope::start_in(collector, name, cat),
+            None => TimeScope::disabled(),
+        }
+    }
+
+    pub(crate) fn start_in(
+        collector: Collector,
+        name: Cow<'static, str>,
+        cat: Cow<'static, str>,
+    ) -> TimeScope {
+        let _paused = alloc::pause();
+
+        // the thread id is generated before the start is measured
+        let tid = get_id();
+        let id = next_span();
+        let key = collector.key();
+
+        // scopes are nested in the scopes of the same collector
+        let (parent, depth) = SPAN_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let parent = stack.iter().rev().find(|span| span.0 == key);
+            let parent = parent.map(|span| span.1);
+            let depth = stack.iter().filter(|span| span.0 == key).count();
+            stack.push((key, id));
+            (parent, depth)
+        });
+
+        let scopes = collector.open_scope(OpenScope {
+            id,
+            parent,
+            depth,
             name,
+            cat,
+            start: collector.timestamp(),
+            tid,
+        });
+        let scopes = match scopes {
+            Some(scopes) => scopes,
+            None => {
+                // the collector has ended
+                SPAN_STACK.with(|stack| stack.borrow_mut().pop());
+                return TimeScope::disabled();
+            }
+        };
+
+        TimeScope {
+            span: Some((collector, id, scopes)),
+            allocs: AllocStats::current(),
+            args: Vec::new(),
+            _thread: PhantomData,
         }
     }
+
+    /// Attaches an argument to the scope
+    ///
+    /// The argument is saved in **args** field of [BenchData::Bench].
+    ///
+    /// [BenchData::Bench]: enum.BenchData.html#variant.Bench
+    pub fn arg(&mut self, name: String, value: Arg) {
+        if self.span.is_some() {
+            self.args.push((name, value));
+        }
+    }
+
+    /// Returns the span id of the scope
+    ///
+    /// Returns `None` if the scope is not collected. This id is saved
+    /// in **id** field of [BenchData::Bench] and in **parent** field
+    /// of the scopes nested in this scope.
+    ///
+    /// [BenchData::Bench]: enum.BenchData.html#variant.Bench
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, scope, BenchData, Writer};
+    ///
+    /// // Checks that "Inner" is nested in "Outer"
+    /// struct TreeCheck;
+    ///
+    /// impl Writer for TreeCheck {
+    ///     fn end(&self, data: &[BenchData]) {
+    ///         let span = |scope: &str| {
+    ///             data.iter()
+    ///                 .find_map(|data| match data {
+    ///                     BenchData::Bench { name, id, parent, depth, .. } if name == scope => {
+    ///                         Some((*id, *parent, *depth))
+    ///                     }
+    ///                     _ => None,
+    ///                 })
+    ///                 .unwrap()
+    ///         };
+    ///
+    ///         let (outer, _, _) = span("Outer");
+    ///         let (_, parent, depth) = span("Inner");
+    ///         assert_eq!(parent, Some(outer));
+    ///         assert_eq!(depth, 1);
+    ///     }
+    /// }
+    ///
+    /// fn main() {
+    ///     instantiate!(TreeCheck);
+    ///
+    ///     scope!(outer | "Outer");
+    ///     {
+    ///         scope!(inner | "Inner");
+    ///     }
+    /// }
+    /// ```
+    pub fn id(&self) -> Option<u64> {
+        self.span.as_ref().map(|span| span.1)
+    }
 }
 
 impl Drop for TimeScope {
     fn drop(&mut self) {
-        bench(mem::replace(&mut self.name, String::new()), self.start);
+        let (collector, id, scopes) = match self.span.take() {
+            Some(span) => span,
+            None => return,
+        };
+        let _paused = alloc::pause();
+
+        // scopes can be dropped in any order
+        let _ = SPAN_STACK.try_with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(pos) = stack.iter().rposition(|span| span.1 == id) {
+                stack.remove(pos);
+            }
+        });
+
+        let mut args = mem::take(&mut self.args);
+
+        if let (Some(start), Some(now)) = (self.allocs, AllocStats::current()) {
+            let AllocStats { allocs, bytes } = now.since(start);
+            args.push((String::from("allocs"), Arg::Int(allocs as i64)));
+            args.push((String::from("alloc bytes"), Arg::Int(bytes as i64)));
+        }
+
+        if thread::panicking() {
+            args.push((String::from("unwound"), Arg::Bool(true)));
+        }
+
+        // the scope could have been ended by the panic hook
+        // or on deinstantiation
+        collector.close_scope(&scopes, id, args);
     }
 }
 
@@ -74,36 +305,280 @@ impl Drop for TimeScope {
 /// and deinstantiates it upon drop. It also is responsible
 /// for calling the writers when the data is collected.
 ///
+/// The global data is the default [Collector], which is used
+/// by the threads that have not entered another collector.
+///
 /// Using [instantiate!] macro instead of this struct is recommened.
 ///
 /// [instantiate!]: macro.instantiate.html
+/// [Collector]: struct.Collector.html
 pub struct Instantiator {
+    collector: Collector,
     alive: bool,
-    writers: Vec<Box<dyn Writer + 'static>>,
+    samplers: Vec<Sampler>,
 }
 
 impl Instantiator {
     /// Constructs the instantiator
     ///
-    /// The writers will be called in [end] method.
+    /// The writers will be called in [end] method. If `GBENCH_FILTER`
+    /// environment variable is present it is used as the [Filter],
+    /// unless the filter was set with [set_filter] before.
+    ///
+    /// The time that one scope adds to the scope it is nested in is
+    /// measured once per process and saved as "scope overhead"
+    /// [BenchData::Meta], so it can be subtracted from the durations
+    /// of the scopes with many nested scopes.
     ///
+    /// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+    /// [Filter]: struct.Filter.html
+    /// [set_filter]: fn.set_filter.html
     /// [end]: struct.Instantiator.html#method.end
     pub fn new(writers: Vec<Box<dyn Writer + 'static>>) -> Instantiator {
-        begin();
+        Instantiator::with_collector(Collector::new(writers))
+    }
+
+    /// Constructs the instantiator that uses `clock` for the timestamps
+    ///
+    /// See [Clock] for the available clocks.
+    ///
+    /// [Clock]: trait.Clock.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{scope, ChromeTracing, Instantiator, TscClock};
+    /// use std::time::Duration;
+    ///
+    /// fn main() {
+    ///     let clock = TscClock::new(Duration::from_millis(10));
+    ///     let _ginst = Instantiator::with_clock(
+    ///         vec![Box::new(ChromeTracing("target/bench"))],
+    ///         Box::new(clock),
+    ///     );
+    ///
+    ///     scope!(sc | "Scope");
+    /// }
+    /// ```
+    pub fn with_clock(
+        writers: Vec<Box<dyn Writer + 'static>>,
+        clock: Box<dyn Clock>,
+    ) -> Instantiator {
+        Instantiator::with_collector(Collector::with_clock(writers, clock))
+    }
+
+    fn with_collector(collector: Collector) -> Instantiator {
+        set_default(Some(collector.clone()));
+
         Instantiator {
+            collector,
             alive: true,
-            writers,
+            samplers: Vec::new(),
         }
     }
 
+    /// Switches the collection to flight recorder mode
+    ///
+    /// Only the events that are in the `window` are kept, so the
+    /// memory usage stays bounded for long running programs. The
+    /// current window can be written with [dump] at any moment,
+    /// for example when an incident is detected.
+    ///
+    /// [dump]: fn.dump.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{dump, instantiate, scope, ChromeTracing, Window};
+    ///
+    /// fn main() {
+    ///     instantiate!(ginst | ChromeTracing("target/bench"));
+    ///     // Keep only the last 100 events
+    ///     ginst.flight_recorder(Window::Events(100));
+    ///
+    ///     for i in 0..10_000 {
+    ///         scope!(sc | "Request {}", i);
+    ///
+    ///         if i == 5_000 {
+    ///             // Writes requests 4900 - 4999
+    ///             dump();
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn flight_recorder(&mut self, window: Window) {
+        self.collector.flight_recorder(window);
+    }
+
+    /// Adds a stream writer that receives the events from now on
+    ///
+    /// See [StreamWriter].
+    ///
+    /// [StreamWriter]: trait.StreamWriter.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, scope, Batch, ChromeTracing, CsvWriter};
+    ///
+    /// fn main() {
+    ///     instantiate!(ginst | );
+    ///     // The events are written to the file as they are collected
+    ///     ginst.stream(ChromeTracing("target/bench"));
+    ///     // Any writer can be added as a stream writer
+    ///     ginst.stream(Batch(CsvWriter("target/bench")));
+    ///
+    ///     for i in 0..1000 {
+    ///         scope!(sc | "Scope {}", i);
+    ///     }
+    /// }
+    /// ```
+    pub fn stream(&mut self, writer: impl StreamWriter) {
+        self.collector.stream(writer);
+    }
+
+    /// Serves the collected events to the clients of `endpoint`
+    ///
+    /// Every client that connects gets the events collected so far
+    /// and then every new event as soon as it is collected, one json
+    /// object per line. The stream ends when the collection ends.
+    /// The clients that do not read fast enough are disconnected, and
+    /// at the end the clients are given a second to read the last
+    /// events. A unix socket that another process is listening on is
+    /// not replaced, the error kind is `AddrInUse` then.
+    /// The events can be read with [LiveReader] or watched with the
+    /// gbench command line tool:
+    ///
+    /// ```text
+    /// gbench tail 127.0.0.1:7000
+    /// ```
+    ///
+    /// Returns the endpoint that is listened on, which has the port
+    /// if port 0 was given.
+    ///
+    /// [LiveReader]: struct.LiveReader.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, scope, BenchData, Endpoint, LiveReader};
+    /// use std::net::TcpStream;
+    ///
+    /// fn main() {
+    ///     instantiate!(ginst | );
+    ///     let endpoint = ginst.listen(Endpoint::Tcp(0)).unwrap();
+    ///
+    ///     let port = match endpoint {
+    ///         Endpoint::Tcp(port) => port,
+    ///         _ => unreachable!(),
+    ///     };
+    ///     let mut events = LiveReader::new(TcpStream::connect(("127.0.0.1", port)).unwrap());
+    ///     // The client gets the metadata first
+    ///     assert!(matches!(events.next(), Some(Ok(BenchData::Meta { .. }))));
+    ///
+    ///     for i in 0..10 {
+    ///         scope!(sc | "Scope {}", i);
+    ///     }
+    ///     ginst.end();
+    ///
+    ///     let scopes = events
+    ///         .filter(|data| matches!(data, Ok(BenchData::Bench { .. })))
+    ///         .count();
+    ///     assert_eq!(scopes, 10);
+    /// }
+    /// ```
+    pub fn listen(&mut self, endpoint: Endpoint) -> io::Result<Endpoint> {
+        self.collector.listen(endpoint)
+    }
+
+    /// Starts collecting the heap size
+    ///
+    /// Every `interval` the number of allocated bytes is saved to
+    /// field "bytes" of counter "Heap". This does nothing if
+    /// [TrackingAllocator] is not the global allocator.
+    ///
+    /// [TrackingAllocator]: struct.TrackingAllocator.html
+    pub fn sample_heap(&mut self, interval: Duration) {
+        self.samplers.push(Sampler::spawn(interval, || {
+            if let Some(bytes) = AllocStats::heap_bytes() {
+                count(
+                    String::from("Heap"),
+                    String::from("count"),
+                    vec![(String::from("bytes"), bytes as f32)],
+                );
+            }
+        }));
+    }
+
+    /// Starts collecting process resource counters
+    ///
+    /// Every `interval` these counters are read from `/proc`:
+    /// - **Memory** - "rss" and "virtual" memory in bytes
+    /// - **CPU time** - "total" process time and time of
+    ///   every thread in milliseconds
+    /// - **Threads** - "count" of threads
+    /// - **File descriptors** - "count" of open file descriptors
+    ///
+    /// This does nothing on systems other than Linux.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, ChromeTracing, CsvWriter};
+    /// use std::time::Duration;
+    ///
+    /// fn main() {
+    ///     instantiate!(ginst | ChromeTracing("target/bench"), CsvWriter("target/bench"));
+    ///     ginst.sample_process(Duration::from_millis(10));
+    ///
+    ///     let v = (0..1_000_000).collect::<Vec<u64>>();
+    ///     std::thread::sleep(Duration::from_millis(50));
+    ///     drop(v);
+    /// }
+    /// ```
+    pub fn sample_process(&mut self, interval: Duration) {
+        self.samplers
+            .push(Sampler::spawn(interval, process::sample));
+    }
+
     /// Deinstantiates global variables and calls the writers
     ///
     /// This method is used when Instantiator is never dropped.
+    ///
+    /// The scopes that have not ended yet, for example the scopes
+    /// of threads that are never joined, are ended at this moment
+    /// and marked with "incomplete" arg.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, scope, ChromeTracing};
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// fn main() {
+    ///     instantiate!(ginst | ChromeTracing("target/bench"));
+    ///
+    ///     // This thread is never joined
+    ///     thread::spawn(|| {
+    ///         scope!(sc | "Background");
+    ///         thread::sleep(Duration::from_secs(10));
+    ///     });
+    ///
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     // "Background" is saved as incomplete
+    ///     ginst.end();
+    /// }
+    /// ```
     // This method is called on drop.
     pub fn end(&mut self) {
         if self.alive {
             self.alive = false;
-            end(mem::replace(&mut self.writers, Vec::new()));
+            for sampler in self.samplers.drain(..) {
+                sampler.stop();
+            }
+            self.collector.end();
+            remove_default(&self.collector);
         }
     }
 }
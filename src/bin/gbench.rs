@@ -0,0 +1,203 @@
+//! Command line tool for gbench traces
+//!
+//! ```text
+//! gbench convert <input.gbench> [output.json]
+//! gbench tail [--raw] <port | host:port | socket path>
+//! ```
+//!
+//! `convert` converts a file of gbench binary format to chrome
+//! tracing format. The output is saved next to the input with
+//! `.json` extension if it is not given.
+//!
+//! `tail` connects to the live stream of a program and prints the
+//! events as they are collected. With `--raw` the json lines are
+//! printed as they are received.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process;
+
+use gbench::{Arg, BenchData, BinaryReader, ChromeTracing, LiveReader};
+
+const USAGE: &str = "usage:
+    gbench convert <input.gbench> [output.json]
+    gbench tail [--raw] <port | host:port | socket path>";
+
+fn fail(message: &str) -> ! {
+    eprintln!("gbench: {}", message);
+    process::exit(1);
+}
+
+fn convert(input: &str, output: Option<&str>) {
+    let output = match output {
+        Some(output) => output.to_string(),
+        None => Path::new(input)
+            .with_extension("json")
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    let file = File::open(input).unwrap_or_else(|e| fail(&format!("{}: {}", input, e)));
+    let reader = BinaryReader::new(file).unwrap_or_else(|e| fail(&format!("{}: {}", input, e)));
+
+    let file = File::create(&output).unwrap_or_else(|e| fail(&format!("{}: {}", output, e)));
+    let mut file = BufWriter::new(file);
+
+    // the events are converted one by one, the conversion
+    // stops at the first event that can not be read
+    let mut error = None;
+    let data = reader.map_while(|data| data.map_err(|e| error = Some(e)).ok());
+    ChromeTracing::write_to(&mut file, data);
+    if let Some(e) = error {
+        drop(file);
+        let _ = fs::remove_file(&output);
+        fail(&format!("{}: {}", input, e));
+    }
+
+    file.flush()
+        .unwrap_or_else(|e| fail(&format!("{}: {}", output, e)));
+}
+
+// connects to the live stream at `address`
+fn connect(address: &str) -> io::Result<Box<dyn Read>> {
+    if let Ok(port) = address.parse::<u16>() {
+        return Ok(Box::new(TcpStream::connect(("127.0.0.1", port))?));
+    }
+
+    #[cfg(unix)]
+    {
+        if address.contains('/') || !address.contains(':') {
+            return Ok(Box::new(UnixStream::connect(address)?));
+        }
+    }
+
+    Ok(Box::new(TcpStream::connect(address)?))
+}
+
+fn time(us: f32) -> String {
+    if us >= 1_000_000.0 {
+        format!("{:.2} s", us / 1_000_000.0)
+    } else if us >= 1_000.0 {
+        format!("{:.2} ms", us / 1_000.0)
+    } else {
+        format!("{:.2} µs", us)
+    }
+}
+
+fn arg(arg: &Arg) -> String {
+    match arg {
+        Arg::Int(v) => v.to_string(),
+        Arg::Float(v) => v.to_string(),
+        Arg::Str(v) => format!("{:?}", v),
+        Arg::Bool(v) => v.to_string(),
+    }
+}
+
+fn args(args: &[(String, Arg)]) -> String {
+    args.iter()
+        .map(|(name, value)| format!(" {}={}", name, arg(value)))
+        .collect()
+}
+
+fn print(out: &mut impl Write, data: &BenchData) -> io::Result<()> {
+    match data {
+        BenchData::Log {
+            log,
+            ts,
+            tid,
+            args: a,
+            ..
+        } => writeln!(
+            out,
+            "{:>12.3} ms  #{:<3} log    {}{}",
+            ts / 1000.0,
+            tid,
+            log,
+            args(a)
+        ),
+        BenchData::Bench {
+            name,
+            ts,
+            dur,
+            tid,
+            args: a,
+            depth,
+            ..
+        } => writeln!(
+            out,
+            "{:>12.3} ms  #{:<3} scope  {:indent$}{} {}{}",
+            ts / 1000.0,
+            tid,
+            "",
+            name,
+            time(*dur),
+            args(a),
+            indent = depth * 2
+        ),
+        BenchData::Count {
+            name,
+            data,
+            ts,
+            tid,
+            ..
+        } => {
+            let fields = data
+                .iter()
+                .map(|(field, value)| format!(" {}={}", field, value))
+                .collect::<String>();
+            writeln!(
+                out,
+                "{:>12.3} ms  #{:<3} count  {}{}",
+                ts / 1000.0,
+                tid,
+                name,
+                fields
+            )
+        }
+        BenchData::Meta { name, value } => writeln!(out, "meta {} = {}", name, arg(value)),
+    }
+}
+
+fn tail(address: &str, raw: bool) {
+    let input = connect(address).unwrap_or_else(|e| fail(&format!("{}: {}", address, e)));
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    // stops quietly when the output is closed
+    let result = if raw {
+        BufReader::new(input).lines().try_for_each(|line| {
+            let line = line?;
+            writeln!(out, "{}", line)?;
+            out.flush()
+        })
+    } else {
+        LiveReader::new(input).try_for_each(|data| {
+            print(&mut out, &data?)?;
+            out.flush()
+        })
+    };
+
+    if let Err(e) = result {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            fail(&format!("{}: {}", address, e));
+        }
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        ["convert", input] => convert(input, None),
+        ["convert", input, output] => convert(input, Some(output)),
+        ["tail", address] => tail(address, false),
+        ["tail", "--raw", address] => tail(address, true),
+        _ => fail(USAGE),
+    }
+}
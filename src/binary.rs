@@ -0,0 +1,688 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+
+use crate::global::{Arg, BenchData};
+use crate::writer::{Output, SessionInfo, StreamWriter, Writer};
+
+// Binary format
+//
+// magic "GBNC", version byte, then records until the end of the input
+//
+// string: 0, length and utf-8 bytes
+// meta:   1, name, arg
+// log:    2, tid, ts, cat, log, args
+// scope:  3, tid, ts, dur, name, cat, id, parent + 1 or 0, depth, args
+// count:  4, tid, ts, name, cat, field count, name and f32 of every field
+// args:   count, then name, tag and value of every arg
+// arg:    0 int, 1 f64, 2 string, 3 bool
+//
+// All the integers are varints and signed integers are zigzag encoded.
+// Strings are indexes of the string records, which are numbered in
+// order and come before the first record that uses them. Timestamps
+// are in nanoseconds and are stored as the difference with the previous
+// event of the same thread. The records are written as the events
+// come, so neither the writer nor the reader keeps the whole trace.
+//
+// The events of the threads are not grouped in blocks. Blocks would
+// make the stream writer buffer the events of every thread, while
+// the thread id and the timestamp relative to the previous event of
+// the thread in every record give the same small timestamps.
+
+const MAGIC: &[u8; 4] = b"GBNC";
+const VERSION: u8 = 1;
+
+const STRING: u8 = 0;
+const META: u8 = 1;
+const LOG: u8 = 2;
+const BENCH: u8 = 3;
+const COUNT: u8 = 4;
+
+const INT: u8 = 0;
+const FLOAT: u8 = 1;
+const STR: u8 = 2;
+const BOOL: u8 = 3;
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn write_signed(out: &mut Vec<u8>, v: i64) {
+    write_varint(out, ((v << 1) ^ (v >> 63)) as u64);
+}
+
+// microseconds to nanoseconds
+fn nanos(us: f32) -> i64 {
+    (us as f64 * 1000.0).round() as i64
+}
+
+// Encoder of the records
+struct Encoder<W: Write> {
+    out: W,
+    indexes: HashMap<String, u64>,
+    // timestamp of the last event of every thread
+    prev: HashMap<usize, i64>,
+    // record that is being encoded
+    record: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(mut out: W) -> io::Result<Encoder<W>> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+
+        Ok(Encoder {
+            out,
+            indexes: HashMap::new(),
+            prev: HashMap::new(),
+            record: Vec::new(),
+        })
+    }
+
+    // writes the string record before the record that uses it
+    fn string(&mut self, s: &str) -> io::Result<()> {
+        let index = match self.indexes.get(s) {
+            Some(index) => *index,
+            None => {
+                let mut record = vec![STRING];
+                write_varint(&mut record, s.len() as u64);
+                record.extend_from_slice(s.as_bytes());
+                self.out.write_all(&record)?;
+
+                let index = self.indexes.len() as u64;
+                self.indexes.insert(s.to_string(), index);
+                index
+            }
+        };
+        write_varint(&mut self.record, index);
+        Ok(())
+    }
+
+    fn args(&mut self, args: &[(String, Arg)]) -> io::Result<()> {
+        write_varint(&mut self.record, args.len() as u64);
+        for (name, value) in args {
+            self.string(name)?;
+            self.arg(value)?;
+        }
+        Ok(())
+    }
+
+    fn arg(&mut self, value: &Arg) -> io::Result<()> {
+        match value {
+            Arg::Int(v) => {
+                self.record.push(INT);
+                write_signed(&mut self.record, *v);
+            }
+            Arg::Float(v) => {
+                self.record.push(FLOAT);
+                self.record.extend_from_slice(&v.to_le_bytes());
+            }
+            Arg::Str(v) => {
+                self.record.push(STR);
+                self.string(v)?;
+            }
+            Arg::Bool(v) => {
+                self.record.push(BOOL);
+                self.record.push(*v as u8);
+            }
+        }
+        Ok(())
+    }
+
+    // thread and timestamp of an event
+    fn time(&mut self, tid: usize, ts: f32) {
+        let prev = self.prev.entry(tid).or_insert(0);
+        write_varint(&mut self.record, tid as u64);
+        write_signed(&mut self.record, nanos(ts) - *prev);
+        *prev = nanos(ts);
+    }
+
+    fn event(&mut self, data: &BenchData) -> io::Result<()> {
+        self.record.clear();
+
+        match data {
+            BenchData::Log {
+                log,
+                cat,
+                ts,
+                tid,
+                args,
+            } => {
+                self.record.push(LOG);
+                self.time(*tid, *ts);
+                self.string(cat)?;
+                self.string(log)?;
+                self.args(args)?;
+            }
+            BenchData::Bench {
+                name,
+                cat,
+                ts,
+                dur,
+                tid,
+                args,
+                id,
+                parent,
+                depth,
+            } => {
+                self.record.push(BENCH);
+                self.time(*tid, *ts);
+                write_varint(&mut self.record, nanos(*dur).max(0) as u64);
+                self.string(name)?;
+                self.string(cat)?;
+                write_varint(&mut self.record, *id);
+                write_varint(&mut self.record, parent.map_or(0, |parent| parent + 1));
+                write_varint(&mut self.record, *depth as u64);
+                self.args(args)?;
+            }
+            BenchData::Count {
+                name,
+                cat,
+                ts,
+                tid,
+                data,
+            } => {
+                self.record.push(COUNT);
+                self.time(*tid, *ts);
+                self.string(name)?;
+                self.string(cat)?;
+                write_varint(&mut self.record, data.len() as u64);
+                for (field, value) in data {
+                    self.string(field)?;
+                    self.record.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            BenchData::Meta { name, value } => {
+                self.record.push(META);
+                self.string(name)?;
+                self.arg(value)?;
+            }
+        }
+
+        self.out.write_all(&self.record)
+    }
+}
+
+/// Encodes the data in gbench binary format
+///
+/// The events are written one by one, the [BenchData::Meta] are
+/// written first.
+///
+/// See [BinaryWriter] for the format.
+///
+/// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+/// [BinaryWriter]: struct.BinaryWriter.html
+pub fn write_binary(out: &mut impl Write, data: &[BenchData]) -> io::Result<()> {
+    let mut encoder = Encoder::new(out)?;
+
+    let (meta, events): (Vec<_>, Vec<_>) = data
+        .iter()
+        .partition(|data| matches!(data, BenchData::Meta { .. }));
+    for data in meta.into_iter().chain(events) {
+        encoder.event(data)?;
+    }
+
+    Ok(())
+}
+
+/// Writer for gbench binary format
+///
+/// The binary format is much smaller and faster to write than
+/// the JSON of [ChromeTracing]. Every string is saved once, the
+/// timestamps are saved as varints relative to the previous event
+/// of the thread and the events are written one by one without
+/// buffering the whole file. The events of all the threads are
+/// written in one sequence rather than in blocks of every thread,
+/// so the writer can also be used as a [StreamWriter]. The files
+/// can be read with [BinaryReader] and converted to chrome tracing
+/// format with the gbench command line tool:
+///
+/// ```text
+/// gbench convert target/bench/bench-1600000000000.gbench
+/// ```
+///
+/// First field is the name of the folder where the
+/// output files will be saved. The folder is created
+/// if it does not exist.
+///
+/// [ChromeTracing]: struct.ChromeTracing.html
+/// [StreamWriter]: trait.StreamWriter.html
+/// [BinaryReader]: struct.BinaryReader.html
+pub struct BinaryWriter(pub &'static str);
+
+impl Writer for BinaryWriter {
+    fn end(&self, data: &[BenchData]) {
+        let mut file = Output::create(self.0, "bench", "gbench", None);
+        write_binary(&mut file, data).unwrap();
+        file.finish();
+    }
+}
+
+// Session of BinaryWriter stream writer
+pub struct BinarySession(Encoder<Output>);
+
+impl StreamWriter for BinaryWriter {
+    type Session = BinarySession;
+
+    fn begin(&self, info: &SessionInfo) -> BinarySession {
+        let file = Output::create(self.0, "bench", "gbench", None);
+        let mut encoder = Encoder::new(file).unwrap();
+        for (name, value) in &info.meta {
+            let meta = BenchData::Meta {
+                name: name.clone(),
+                value: value.clone(),
+            };
+            encoder.event(&meta).unwrap();
+        }
+        BinarySession(encoder)
+    }
+
+    fn event(&self, session: &mut BinarySession, data: &BenchData) {
+        // the metadata is written at the beginning
+        if let BenchData::Meta { .. } = data {
+            return;
+        }
+        session.0.event(data).unwrap();
+    }
+
+    fn finish(&self, session: BinarySession) {
+        session.0.out.finish();
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reader of gbench binary format
+///
+/// The reader yields the events in the order they were written,
+/// which is the [BenchData::Meta] first and then the events of
+/// all the threads in the order they were collected.
+///
+/// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{write_binary, BenchData, BinaryReader};
+///
+/// fn main() {
+///     let data = vec![BenchData::Log {
+///         log: String::from("Started"),
+///         cat: String::from("log"),
+///         ts: 10.0,
+///         tid: 0,
+///         args: Vec::new(),
+///     }];
+///
+///     let mut file = Vec::new();
+///     write_binary(&mut file, &data).unwrap();
+///
+///     let read = BinaryReader::new(&file[..])
+///         .unwrap()
+///         .collect::<Result<Vec<_>, _>>()
+///         .unwrap();
+///     assert!(matches!(&read[0], BenchData::Log { log, ts, .. } if log == "Started" && *ts == 10.0));
+/// }
+/// ```
+pub struct BinaryReader<R: Read> {
+    input: BufReader<R>,
+    strings: Vec<String>,
+    // timestamp of the last event of every thread
+    prev: HashMap<usize, i64>,
+    // whether the rest of the input can be read
+    valid: bool,
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Reads the header of the file
+    ///
+    /// Returns an error if the input is not in gbench binary
+    /// format or its version is not supported.
+    pub fn new(input: R) -> io::Result<BinaryReader<R>> {
+        let mut reader = BinaryReader {
+            input: BufReader::new(input),
+            strings: Vec::new(),
+            prev: HashMap::new(),
+            valid: true,
+        };
+
+        let mut magic = [0; 4];
+        reader.input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a gbench binary file"));
+        }
+        if reader.byte()? != VERSION {
+            return Err(invalid("unsupported gbench binary version"));
+        }
+
+        Ok(reader)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(v);
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    fn signed(&mut self) -> io::Result<i64> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    // reads a string record
+    fn define_string(&mut self) -> io::Result<()> {
+        let len = self.varint()?;
+
+        // the length is not trusted before the bytes are read
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        let s = String::from_utf8(bytes).map_err(|_| invalid("invalid string"))?;
+        self.strings.push(s);
+        Ok(())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let index = self.varint()?;
+        match usize::try_from(index)
+            .ok()
+            .and_then(|index| self.strings.get(index))
+        {
+            Some(s) => Ok(s.clone()),
+            None => Err(invalid("invalid string index")),
+        }
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        let mut bytes = [0; 4];
+        self.input.read_exact(&mut bytes)?;
+        Ok(f32::from_le_bytes(bytes))
+    }
+
+    fn arg(&mut self) -> io::Result<Arg> {
+        Ok(match self.byte()? {
+            INT => Arg::Int(self.signed()?),
+            FLOAT => {
+                let mut bytes = [0; 8];
+                self.input.read_exact(&mut bytes)?;
+                Arg::Float(f64::from_le_bytes(bytes))
+            }
+            STR => Arg::Str(self.string()?),
+            BOOL => Arg::Bool(self.byte()? != 0),
+            _ => return Err(invalid("invalid arg")),
+        })
+    }
+
+    fn args(&mut self) -> io::Result<Vec<(String, Arg)>> {
+        let mut args = Vec::new();
+        for _ in 0..self.varint()? {
+            args.push((self.string()?, self.arg()?));
+        }
+        Ok(args)
+    }
+
+    fn tid(&mut self) -> io::Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| invalid("invalid thread id"))
+    }
+
+    // timestamp of an event of thread `tid` in microseconds
+    fn ts(&mut self, tid: usize) -> io::Result<f32> {
+        let delta = self.signed()?;
+        let prev = self.prev.entry(tid).or_insert(0);
+        *prev = prev.wrapping_add(delta);
+        Ok(*prev as f32 / 1000.0)
+    }
+
+    fn next_event(&mut self) -> io::Result<Option<BenchData>> {
+        loop {
+            // the input can only end between the records
+            if self.input.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            let data = match self.byte()? {
+                STRING => {
+                    self.define_string()?;
+                    continue;
+                }
+                META => BenchData::Meta {
+                    name: self.string()?,
+                    value: self.arg()?,
+                },
+                LOG => {
+                    let tid = self.tid()?;
+                    BenchData::Log {
+                        ts: self.ts(tid)?,
+                        cat: self.string()?,
+                        log: self.string()?,
+                        args: self.args()?,
+                        tid,
+                    }
+                }
+                BENCH => {
+                    let tid = self.tid()?;
+                    BenchData::Bench {
+                        ts: self.ts(tid)?,
+                        dur: self.varint()? as f32 / 1000.0,
+                        name: Cow::Owned(self.string()?),
+                        cat: Cow::Owned(self.string()?),
+                        id: self.varint()?,
+                        parent: self.varint()?.checked_sub(1),
+                        depth: self.varint()? as usize,
+                        args: self.args()?,
+                        tid,
+                    }
+                }
+                COUNT => {
+                    let tid = self.tid()?;
+                    let ts = self.ts(tid)?;
+                    let name = self.string()?;
+                    let cat = self.string()?;
+                    let mut data = Vec::new();
+                    for _ in 0..self.varint()? {
+                        data.push((self.string()?, self.f32()?));
+                    }
+                    BenchData::Count {
+                        name,
+                        cat,
+                        ts,
+                        tid,
+                        data,
+                    }
+                }
+                _ => return Err(invalid("invalid record")),
+            };
+
+            return Ok(Some(data));
+        }
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = io::Result<BenchData>;
+
+    fn next(&mut self) -> Option<io::Result<BenchData>> {
+        if !self.valid {
+            return None;
+        }
+
+        match self.next_event() {
+            Ok(data) => data.map(Ok),
+            Err(e) => {
+                // the rest of the input can not be read
+                self.valid = false;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[BenchData]) -> Vec<BenchData> {
+        let mut file = Vec::new();
+        write_binary(&mut file, data).unwrap();
+        BinaryReader::new(&file[..])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    fn scope(ts: f32, tid: usize, id: u64, parent: Option<u64>, depth: usize) -> BenchData {
+        BenchData::Bench {
+            name: Cow::Owned(format!("Scope {}", id)),
+            cat: Cow::Borrowed("function"),
+            ts,
+            dur: 2.5,
+            tid,
+            args: vec![
+                (String::from("int"), Arg::Int(-7)),
+                (String::from("float"), Arg::Float(0.25)),
+                (String::from("str"), Arg::Str(String::from("a \"b\""))),
+                (String::from("bool"), Arg::Bool(true)),
+            ],
+            id,
+            parent,
+            depth,
+        }
+    }
+
+    #[test]
+    fn scopes() {
+        let data = vec![
+            scope(10.0, 0, 1, None, 0),
+            scope(11.0, 0, 2, Some(1), 1),
+            scope(12.5, 1, 3, Some(2), 2),
+        ];
+        assert_eq!(format!("{:?}", round_trip(&data)), format!("{:?}", data));
+    }
+
+    #[test]
+    fn counts_and_meta() {
+        let data = vec![
+            BenchData::Count {
+                name: String::from("Frames"),
+                cat: String::from("count"),
+                ts: 3.0,
+                tid: 2,
+                data: vec![(String::from("drawn"), 4.5), (String::from("lost"), -1.0)],
+            },
+            BenchData::Meta {
+                name: String::from("scope overhead"),
+                value: Arg::Float(0.05),
+            },
+        ];
+
+        // the metadata is written first
+        let read = round_trip(&data);
+        assert_eq!(format!("{:?}", read[0]), format!("{:?}", data[1]));
+        assert_eq!(format!("{:?}", read[1]), format!("{:?}", data[0]));
+    }
+
+    #[test]
+    fn negative_deltas() {
+        let data = vec![
+            scope(100.0, 0, 1, None, 0),
+            scope(40.0, 0, 2, None, 0),
+            scope(-5.0, 1, 3, None, 0),
+            scope(-20.0, 0, 4, None, 0),
+        ];
+        let read = round_trip(&data)
+            .into_iter()
+            .map(|data| match data {
+                BenchData::Bench { ts, tid, .. } => (ts, tid),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(read, vec![(100.0, 0), (40.0, 0), (-5.0, 1), (-20.0, 0)]);
+    }
+
+    #[test]
+    fn stream() {
+        let folder = "target/binary-stream-test";
+        let _ = std::fs::remove_dir_all(folder);
+
+        let data = vec![
+            BenchData::Meta {
+                name: String::from("scope overhead"),
+                value: Arg::Float(0.05),
+            },
+            scope(10.0, 0, 1, None, 0),
+            scope(11.0, 1, 2, None, 0),
+        ];
+        let info = SessionInfo {
+            start: std::time::SystemTime::now(),
+            meta: vec![(String::from("scope overhead"), Arg::Float(0.05))],
+        };
+
+        let writer = BinaryWriter(folder);
+        let mut session = writer.begin(&info);
+        for data in &data[1..] {
+            writer.event(&mut session, data);
+        }
+        writer.finish(session);
+
+        let path = std::fs::read_dir(folder)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let file = std::fs::File::open(path).unwrap();
+        let read = BinaryReader::new(file)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", data));
+    }
+
+    #[test]
+    fn invalid_string_length() {
+        let mut file = Vec::new();
+        file.extend_from_slice(MAGIC);
+        file.push(VERSION);
+        file.push(STRING);
+        write_varint(&mut file, u64::MAX);
+        file.extend_from_slice(b"short");
+
+        let mut reader = BinaryReader::new(&file[..]).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn truncated_record() {
+        let mut file = Vec::new();
+        write_binary(&mut file, &[scope(1.0, 0, 1, None, 0)]).unwrap();
+        file.pop();
+
+        let read = BinaryReader::new(&file[..]).unwrap().collect::<Vec<_>>();
+        assert_eq!(read.len(), 1);
+        assert!(read[0].is_err());
+    }
+}
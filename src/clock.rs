@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Source of the timestamps of the collected data
+///
+/// The timestamps are measured from the moment the global
+/// variables are instantiated, so the clock only has to be
+/// monotonic. The clock is chosen with [Instantiator::with_clock].
+///
+/// [Instantiator::with_clock]: struct.Instantiator.html#method.with_clock
+pub trait Clock: Send + Sync {
+    /// Returns the time since an arbitrary moment
+    fn now(&self) -> Duration;
+}
+
+/// Clock based on `std::time::Instant`
+///
+/// This is the default clock.
+pub struct InstantClock(Instant);
+
+impl InstantClock {
+    /// Constructs the clock
+    pub fn new() -> InstantClock {
+        InstantClock(Instant::now())
+    }
+}
+
+impl Default for InstantClock {
+    fn default() -> InstantClock {
+        InstantClock::new()
+    }
+}
+
+impl Clock for InstantClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// Clock based on the time stamp counter of the processor
+///
+/// Reading the time stamp counter is cheaper than reading
+/// `Instant`. The frequency of the counter is calibrated against
+/// `Instant` on construction, which takes `calibration` time. This
+/// clock requires a processor with an invariant time stamp counter
+/// and is available on x86_64.
+#[cfg(target_arch = "x86_64")]
+pub struct TscClock {
+    start: u64,
+    ticks_per_ns: f64,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[allow(unused_unsafe)]
+fn rdtsc() -> u64 {
+    unsafe { std::arch::x86_64::_rdtsc() }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl TscClock {
+    /// Constructs the clock, calibrating it for `calibration`
+    pub fn new(calibration: Duration) -> TscClock {
+        let instant = Instant::now();
+        let start = rdtsc();
+
+        while instant.elapsed() < calibration {}
+
+        let ticks = rdtsc() - start;
+        let ns = instant.elapsed().as_nanos() as f64;
+
+        TscClock {
+            start,
+            ticks_per_ns: ticks as f64 / ns,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Clock for TscClock {
+    fn now(&self) -> Duration {
+        let ticks = rdtsc().saturating_sub(self.start);
+        Duration::from_nanos((ticks as f64 / self.ticks_per_ns) as u64)
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct Timespec {
+    tv_sec: std::os::raw::c_long,
+    tv_nsec: std::os::raw::c_long,
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn clock_gettime(clock: i32, time: *mut Timespec) -> i32;
+}
+
+#[cfg(target_os = "linux")]
+const CLOCK_MONOTONIC_COARSE: i32 = 6;
+
+/// Clock based on `CLOCK_MONOTONIC_COARSE`
+///
+/// This clock is cheaper to read than `Instant`, but its resolution
+/// is a few milliseconds. It is available on Linux.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct CoarseClock;
+
+#[cfg(target_os = "linux")]
+impl Clock for CoarseClock {
+    fn now(&self) -> Duration {
+        let mut time = Timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { clock_gettime(CLOCK_MONOTONIC_COARSE, &mut time) };
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    }
+}
+
+/// Clock that only moves when it is advanced
+///
+/// This clock makes the timestamps reproducible, which is useful
+/// for testing writers. The clones of the clock share the time,
+/// so a clone can be kept to advance the clock that is given to
+/// the [Instantiator].
+///
+/// [Instantiator]: struct.Instantiator.html
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{BenchData, Instantiator, ManualClock, TimeScope, Writer};
+/// use std::time::Duration;
+///
+/// struct DurationCheck;
+///
+/// impl Writer for DurationCheck {
+///     fn end(&self, data: &[BenchData]) {
+///         let scope = data.iter().find_map(|data| match data {
+///             BenchData::Bench { ts, dur, .. } => Some((*ts, *dur)),
+///             _ => None,
+///         });
+///         assert_eq!(scope, Some((10.0, 5.0)));
+///     }
+/// }
+///
+/// fn main() {
+///     let clock = ManualClock::new();
+///     let ginst = Instantiator::with_clock(vec![Box::new(DurationCheck)], Box::new(clock.clone()));
+///
+///     clock.advance(Duration::from_micros(10));
+///     let scope = TimeScope::new(String::from("Scope"));
+///     clock.advance(Duration::from_micros(5));
+///     drop(scope);
+///
+///     drop(ginst);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    /// Constructs the clock at zero
+    pub fn new() -> ManualClock {
+        ManualClock(Arc::new(AtomicU64::new(0)))
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, time: Duration) {
+        self.0.fetch_add(time.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Sets the time of the clock
+    pub fn set(&self, time: Duration) {
+        self.0.store(time.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::SeqCst))
+    }
+}
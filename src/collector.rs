@@ -0,0 +1,655 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{
+    Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    TryLockError, Weak,
+};
+use std::time::{Duration, SystemTime};
+
+use crate::bench::TimeScope;
+use crate::clock::{Clock, InstantClock};
+use crate::filter;
+use crate::global::{self, get_id, Arg, BenchData, OpenScope};
+use crate::live::{self, Endpoint};
+use crate::panic;
+use crate::queue::{Queue, Window};
+use crate::writer::{SessionInfo, Stream, StreamWriter, Writer};
+
+// number of scopes used to measure the overhead
+const CALIBRATION_SCOPES: u32 = 1000;
+
+// overhead of the scopes with the default clock, which is
+// measured by the first collector that uses it
+static OVERHEAD: OnceLock<f32> = OnceLock::new();
+
+// gives every collector an id that is not reused
+static NEXT_COLLECTOR: AtomicU64 = AtomicU64::new(0);
+
+// scopes of one thread in one collector that are not ended
+pub(crate) type ThreadScopes = Arc<Mutex<Vec<OpenScope>>>;
+
+thread_local! {
+    // scopes of the thread in each collector it has started scopes in
+    static THREAD_SCOPES: RefCell<Vec<(u64, Weak<Inner>, ThreadScopes)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+// locks `mutex`, but only if it is free when called from the panic
+// hook, as the panicking thread could be holding the lock, the lock
+// is poisoned if a writer panics, which does not end the collection
+fn lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    if !panic::in_hook() {
+        return Some(mutex.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+// read locks `lock` in the same way as `lock`
+fn read<T>(lock: &RwLock<T>) -> Option<RwLockReadGuard<'_, T>> {
+    if !panic::in_hook() {
+        return Some(lock.read().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    match lock.try_read() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+// write locks `lock` in the same way as `lock`
+fn write<T>(lock: &RwLock<T>) -> Option<RwLockWriteGuard<'_, T>> {
+    if !panic::in_hook() {
+        return Some(lock.write().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    match lock.try_write() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+// Collected data with the clock that timestamps it, which is
+// shared with the live clients, so they can be served at the end
+pub(crate) struct Store {
+    clock: Box<dyn Clock>,
+    begin: Duration,
+    queue: Mutex<Queue>,
+}
+
+impl Store {
+    // timestamp of the current moment
+    fn timestamp(&self) -> f32 {
+        let elapsed = self.clock.now().saturating_sub(self.begin);
+        (elapsed.as_nanos() as f32) / 1000.0
+    }
+
+    // copy of the collected data with the sequence number of the
+    // last event in it, the streams get the events after it
+    pub(crate) fn sequenced_snapshot(&self) -> (Vec<BenchData>, u64) {
+        let now = self.timestamp();
+        let mut queue = self.queue.lock().unwrap();
+        (queue.snapshot(now), queue.pushed())
+    }
+}
+
+struct Inner {
+    id: u64,
+    store: Arc<Store>,
+    start: SystemTime,
+    writers: Mutex<Vec<Box<dyn Writer + 'static>>>,
+    streams: RwLock<Vec<Stream>>,
+    // scopes that are not ended, one list per thread
+    threads: Mutex<Vec<Weak<Mutex<Vec<OpenScope>>>>>,
+    ended: AtomicBool,
+}
+
+impl Inner {
+    fn push(&self, data: BenchData) {
+        // a stream is not added while the event is pushed, so it
+        // gets the events that are pushed after it is added
+        let streams = match read(&self.streams) {
+            Some(streams) => streams,
+            None => return,
+        };
+        let shared = (!streams.is_empty()).then(|| Arc::new(data.clone()));
+
+        let seq = match lock(&self.store.queue) {
+            Some(mut queue) => queue.push(data, self.timestamp()),
+            None => return,
+        };
+
+        // the streams write the events on their own threads
+        if let Some(shared) = shared {
+            for stream in streams.iter() {
+                stream.event(seq, &shared);
+            }
+        }
+    }
+
+    fn write(&self, data: &[BenchData]) {
+        if let Some(writers) = lock(&self.writers) {
+            for writer in writers.iter() {
+                writer.end(data);
+            }
+        }
+    }
+
+    // ends the scopes of all threads, marking them with `arg`
+    fn close_scopes(&self, arg: &str) {
+        let threads = match lock(&self.threads) {
+            Some(threads) => threads.iter().filter_map(Weak::upgrade).collect::<Vec<_>>(),
+            None => return,
+        };
+
+        let mut scopes = Vec::new();
+        for thread in &threads {
+            if let Some(mut thread) = lock(thread) {
+                scopes.append(&mut thread);
+            }
+        }
+        scopes.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        let ts = self.timestamp();
+        for scope in scopes {
+            let args = vec![(String::from(arg), Arg::Bool(true))];
+            self.push(bench_data(scope, ts, args));
+        }
+    }
+
+    fn end(&self) {
+        if self.ended.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        self.close_scopes("incomplete");
+
+        // before the data is taken, so the live clients
+        // that connected get all of it
+        self.finish_streams();
+
+        let now = self.timestamp();
+        let data = lock(&self.store.queue).map(|mut queue| queue.take(now));
+        if let Some(data) = data {
+            self.write(&data);
+        }
+    }
+
+    // the streams are finished outside of the lock, as they
+    // can take some time to write the last events
+    fn finish_streams(&self) {
+        let streams = match write(&self.streams) {
+            Some(mut streams) => mem::take(&mut *streams),
+            None => return,
+        };
+        for stream in streams {
+            stream.finish();
+        }
+    }
+
+    fn timestamp(&self) -> f32 {
+        self.store.timestamp()
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+// event of a scope that ended at `ts`
+fn bench_data(scope: OpenScope, ts: f32, args: Vec<(String, Arg)>) -> BenchData {
+    let OpenScope {
+        id,
+        parent,
+        depth,
+        name,
+        cat,
+        start,
+        tid,
+    } = scope;
+
+    BenchData::Bench {
+        name,
+        cat,
+        ts: start,
+        dur: ts - start,
+        tid,
+        args,
+        id,
+        parent,
+        depth,
+    }
+}
+
+/// Collector of the benchmarking data
+///
+/// Collector owns the collected data, the [Clock] and the writers, so
+/// several collectors can be used in the same process, for example by
+/// parallel tests or by a library that benchmarks itself. The clones
+/// of a collector share the data. When the collector is ended or its
+/// last clone is dropped the collected data is given to the writers.
+///
+/// The scopes can be started in a collector with [scope], or the
+/// collector can be entered with [enter], so [scope!], [log!] and
+/// [count!] use it on the current thread. Otherwise they use the
+/// default collector of [Instantiator].
+///
+/// [Clock]: trait.Clock.html
+/// [scope]: struct.Collector.html#method.scope
+/// [enter]: struct.Collector.html#method.enter
+/// [scope!]: macro.scope.html
+/// [log!]: macro.log.html
+/// [count!]: macro.count.html
+/// [Instantiator]: struct.Instantiator.html
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{scope, BenchData, Collector, Writer};
+/// use std::thread;
+///
+/// // Checks that only the scopes of one collector are collected
+/// struct CountCheck(usize);
+///
+/// impl Writer for CountCheck {
+///     fn end(&self, data: &[BenchData]) {
+///         let scopes = data
+///             .iter()
+///             .filter(|data| matches!(data, BenchData::Bench { .. }))
+///             .count();
+///         assert_eq!(scopes, self.0);
+///     }
+/// }
+///
+/// fn main() {
+///     let threads = (1..=4).map(|n| {
+///         thread::spawn(move || {
+///             let collector = Collector::new(vec![Box::new(CountCheck(n + 1))]);
+///             let _outer = collector.scope("Outer");
+///
+///             let _guard = collector.enter();
+///             for i in 0..n {
+///                 scope!(sc | "Inner {}", i);
+///             }
+///         })
+///     });
+///
+///     for thread in threads.collect::<Vec<_>>() {
+///         thread.join().unwrap();
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Collector(Arc<Inner>);
+
+impl Collector {
+    /// Constructs the collector
+    ///
+    /// If `GBENCH_FILTER` environment variable is present it is used
+    /// as the [Filter], unless the filter was set with [set_filter]
+    /// before. The time that one scope adds to the scope it
+    /// is nested in is measured once per process and saved as
+    /// "scope overhead" [BenchData::Meta], so it can be subtracted
+    /// from the durations of the scopes with many nested scopes.
+    ///
+    /// [Filter]: struct.Filter.html
+    /// [set_filter]: fn.set_filter.html
+    /// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+    pub fn new(writers: Vec<Box<dyn Writer + 'static>>) -> Collector {
+        let collector = Collector::build(writers, Box::new(InstantClock::new()));
+        let overhead = *OVERHEAD.get_or_init(|| collector.calibrate());
+        collector.push_overhead(overhead);
+        collector
+    }
+
+    /// Constructs the collector that uses `clock` for the timestamps
+    ///
+    /// The "scope overhead" is measured with `clock` on every
+    /// construction, as it is different for every clock.
+    pub fn with_clock(writers: Vec<Box<dyn Writer + 'static>>, clock: Box<dyn Clock>) -> Collector {
+        let collector = Collector::build(writers, clock);
+        let overhead = collector.calibrate();
+        collector.push_overhead(overhead);
+        collector
+    }
+
+    fn build(writers: Vec<Box<dyn Writer + 'static>>, clock: Box<dyn Clock>) -> Collector {
+        filter::filter_from_env();
+
+        let begin = clock.now();
+        Collector(Arc::new(Inner {
+            id: NEXT_COLLECTOR.fetch_add(1, Ordering::Relaxed),
+            store: Arc::new(Store {
+                clock,
+                begin,
+                queue: Mutex::new(Queue::new()),
+            }),
+            start: SystemTime::now(),
+            writers: Mutex::new(writers),
+            streams: RwLock::new(Vec::new()),
+            threads: Mutex::new(Vec::new()),
+            ended: AtomicBool::new(false),
+        }))
+    }
+
+    fn push_overhead(&self, overhead: f32) {
+        self.push(BenchData::Meta {
+            name: String::from("scope overhead"),
+            value: Arg::Float(overhead as f64),
+        });
+    }
+
+    /// Starts a scope in this collector
+    ///
+    /// The scope has category "function" and is not collected if
+    /// its name is filtered out by the [Filter].
+    ///
+    /// [Filter]: struct.Filter.html
+    pub fn scope(&self, name: impl Into<Cow<'static, str>>) -> TimeScope {
+        let name = name.into();
+        if filter::enabled(&name, "function") {
+            TimeScope::start_in(self.clone(), name, Cow::Borrowed("function"))
+        } else {
+            TimeScope::disabled()
+        }
+    }
+
+    /// Makes this collector current for the thread
+    ///
+    /// Until the guard is dropped the macros of the thread use this
+    /// collector instead of the default one.
+    pub fn enter(&self) -> CollectorGuard {
+        global::enter(self.clone());
+        CollectorGuard {
+            collector: self.clone(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Adds a stream writer that receives the events from now on
+    ///
+    /// See [StreamWriter].
+    ///
+    /// [StreamWriter]: trait.StreamWriter.html
+    pub fn stream(&self, writer: impl StreamWriter) {
+        self.add_stream(|info| Stream::begin(writer, info));
+    }
+
+    // adds the stream made by `stream` with the session info
+    pub(crate) fn add_stream(&self, stream: impl FnOnce(SessionInfo) -> Stream) {
+        let mut streams = self.0.streams.write().unwrap();
+        let meta = self
+            .0
+            .store
+            .queue
+            .lock()
+            .unwrap()
+            .meta()
+            .iter()
+            .filter_map(|data| match data {
+                BenchData::Meta { name, value } => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+        let info = SessionInfo {
+            start: self.0.start,
+            meta,
+        };
+
+        streams.push(stream(info));
+    }
+
+    /// Serves the collected events to the clients of `endpoint`
+    ///
+    /// See [Instantiator::listen].
+    ///
+    /// [Instantiator::listen]: struct.Instantiator.html#method.listen
+    pub fn listen(&self, endpoint: Endpoint) -> io::Result<Endpoint> {
+        live::listen(self, endpoint)
+    }
+
+    /// Switches the collection to flight recorder mode
+    ///
+    /// See [Instantiator::flight_recorder].
+    ///
+    /// [Instantiator::flight_recorder]: struct.Instantiator.html#method.flight_recorder
+    pub fn flight_recorder(&self, window: Window) {
+        let now = self.timestamp();
+        self.0.store.queue.lock().unwrap().set_window(window, now);
+    }
+
+    /// Writes the collected data without ending the collection
+    ///
+    /// See [dump].
+    ///
+    /// [dump]: fn.dump.html
+    pub fn dump(&self) {
+        let now = self.timestamp();
+        let data = match lock(&self.0.store.queue) {
+            Some(mut queue) => queue.snapshot(now),
+            None => return,
+        };
+        self.0.write(&data);
+    }
+
+    /// Returns a copy of the collected data
+    ///
+    /// See [snapshot].
+    ///
+    /// [snapshot]: fn.snapshot.html
+    pub fn snapshot(&self) -> Vec<BenchData> {
+        let now = self.timestamp();
+        self.0.store.queue.lock().unwrap().snapshot(now)
+    }
+
+    /// Takes the collected data out of the collector
+    ///
+    /// See [drain].
+    ///
+    /// [drain]: fn.drain.html
+    pub fn drain(&self) -> Vec<BenchData> {
+        let now = self.timestamp();
+        self.0.store.queue.lock().unwrap().drain(now)
+    }
+
+    /// Ends the collection and gives the collected data to the writers
+    ///
+    /// The scopes that have not ended yet are ended at this moment and
+    /// marked with "incomplete" arg. The events that happen after
+    /// this are not collected. This is called when the last clone of
+    /// the collector is dropped.
+    pub fn end(&self) {
+        self.0.end();
+    }
+
+    // collected data that is kept while it is used, even if
+    // the collector is dropped
+    pub(crate) fn store(&self) -> Arc<Store> {
+        self.0.store.clone()
+    }
+
+    pub(crate) fn ptr_eq(&self, other: &Collector) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    // identifies the collector, the ids are not reused
+    pub(crate) fn key(&self) -> u64 {
+        self.0.id
+    }
+
+    // timestamp of the current moment
+    pub(crate) fn timestamp(&self) -> f32 {
+        self.0.timestamp()
+    }
+
+    fn push(&self, data: BenchData) {
+        if !self.0.ended.load(Ordering::Relaxed) {
+            self.0.push(data);
+        }
+    }
+
+    pub(crate) fn log(&self, log: String, cat: String, args: Vec<(String, Arg)>) {
+        let ts = self.timestamp();
+        let tid = get_id();
+
+        self.push(BenchData::Log {
+            log,
+            cat,
+            ts,
+            tid,
+            args,
+        });
+    }
+
+    pub(crate) fn count(&self, name: String, cat: String, data: Vec<(String, f32)>) {
+        let ts = self.timestamp();
+        let tid = get_id();
+
+        self.push(BenchData::Count {
+            name,
+            cat,
+            data,
+            ts,
+            tid,
+        });
+    }
+
+    // registers a started scope, returns the list of the
+    // scopes of the thread the scope is registered in
+    pub(crate) fn open_scope(&self, scope: OpenScope) -> Option<ThreadScopes> {
+        if self.0.ended.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let scopes = THREAD_SCOPES
+            .try_with(|threads| {
+                let mut threads = threads.borrow_mut();
+                let found = threads.iter().find(|thread| thread.0 == self.0.id);
+                if let Some((_, _, scopes)) = found {
+                    return scopes.clone();
+                }
+
+                // the lists of the dropped collectors are removed
+                threads.retain(|thread| thread.1.strong_count() > 0);
+                let scopes = self.register_thread();
+                threads.push((self.0.id, Arc::downgrade(&self.0), scopes.clone()));
+                scopes
+            })
+            .unwrap_or_else(|_| self.register_thread());
+
+        scopes.lock().unwrap().push(scope);
+        Some(scopes)
+    }
+
+    // adds a list of the open scopes of a thread
+    fn register_thread(&self) -> ThreadScopes {
+        let scopes = ThreadScopes::default();
+        let mut threads = self.0.threads.lock().unwrap();
+        threads.retain(|thread| thread.strong_count() > 0);
+        threads.push(Arc::downgrade(&scopes));
+        scopes
+    }
+
+    // ends a scope, does nothing if the scope was
+    // already ended by end_open_scopes or end
+    pub(crate) fn close_scope(&self, scopes: &ThreadScopes, id: u64, args: Vec<(String, Arg)>) {
+        let scope = {
+            let mut scopes = scopes.lock().unwrap();
+            let pos = scopes.iter().rposition(|scope| scope.id == id);
+            pos.map(|pos| scopes.remove(pos))
+        };
+        if let Some(scope) = scope {
+            let ts = self.timestamp();
+            self.push(bench_data(scope, ts, args));
+        }
+    }
+
+    // ends the scopes of all threads, marking them with `arg`
+    pub(crate) fn end_open_scopes(&self, arg: &str) {
+        self.0.close_scopes(arg);
+    }
+
+    // waits until the stream writers have written the events and
+    // finishes them, the events after this are not streamed
+    pub(crate) fn finish_streams(&self) {
+        self.0.finish_streams();
+    }
+
+    // measures the time one scope adds to the duration
+    // of the scope it is nested in in microseconds
+    fn calibrate(&self) -> f32 {
+        let start = self.timestamp();
+        for _ in 0..CALIBRATION_SCOPES {
+            let _scope = TimeScope::start_in(
+                self.clone(),
+                Cow::Borrowed("calibration"),
+                Cow::Borrowed("gbench"),
+            );
+        }
+        let overhead = (self.timestamp() - start) / CALIBRATION_SCOPES as f32;
+
+        // calibration scopes are not a part of the trace
+        self.0.store.queue.lock().unwrap().clear();
+
+        overhead
+    }
+}
+
+/// Guard that makes a [Collector] current for the thread
+///
+/// The previous collector becomes current again when the guard
+/// is dropped. The guards can be dropped in any order, a guard
+/// only removes the collector it has entered. See [Collector::enter].
+///
+/// [Collector]: struct.Collector.html
+/// [Collector::enter]: struct.Collector.html#method.enter
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{scope, BenchData, Collector};
+///
+/// let scopes = |collector: &Collector| {
+///     let data = collector.snapshot();
+///     data.iter().filter(|data| matches!(data, BenchData::Bench { .. })).count()
+/// };
+///
+/// let first = Collector::new(Vec::new());
+/// let second = Collector::new(Vec::new());
+///
+/// let first_guard = first.enter();
+/// let second_guard = second.enter();
+/// drop(first_guard);
+///
+/// {
+///     // Still collected by the second collector
+///     scope!(sc | "Scope");
+/// }
+/// assert_eq!(scopes(&first), 0);
+/// assert_eq!(scopes(&second), 1);
+///
+/// drop(second_guard);
+/// ```
+pub struct CollectorGuard {
+    collector: Collector,
+    // the guard has to be dropped on the thread it was created on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for CollectorGuard {
+    fn drop(&mut self) {
+        global::exit(&self.collector);
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+
+use crate::global::BenchData;
+use crate::report::{format_time, overhead, scope_tree, Node};
+use crate::writer::Writer;
+
+// width of the terminal if it is not known
+const DEFAULT_WIDTH: usize = 80;
+// width of a column of numbers
+const COLUMN: usize = 11;
+// narrowest name column, the columns of numbers are
+// dropped from the right if they do not fit next to it
+const MIN_NAME: usize = 8;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+
+/// Stream that [ConsoleWriter] prints to
+///
+/// [ConsoleWriter]: struct.ConsoleWriter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr,
+}
+
+/// Writer that prints a summary to the terminal
+///
+/// The summary is the tree of the scopes of all threads, where the
+/// scopes with the same stack of names are merged, with their total
+/// time, self time and count, followed by the minimum, maximum and
+/// last values of the counters. The "scope overhead" measured by the
+/// [Collector] is subtracted from the times of the scopes that have
+/// nested scopes.
+///
+/// The lines are cut to the width of the terminal, which is taken
+/// from `COLUMNS` environment variable. If the terminal is too narrow
+/// the columns are dropped starting from the last one. The output is colored if it
+/// goes to a terminal and `NO_COLOR` environment variable is not set.
+///
+/// [Collector]: struct.Collector.html
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{count, instantiate, scope, Console, ConsoleWriter};
+///
+/// fn main() {
+///     // Prints the 5 longest scopes of every level
+///     instantiate!(ConsoleWriter::new(Console::Stderr).top(5));
+///
+///     for i in 0..10 {
+///         scope!(frame | "Frame");
+///         for j in 0..i {
+///             scope!(draw | "Draw {}", j);
+///         }
+///         count!("Frames" => {"drawn" => i});
+///     }
+/// }
+/// ```
+///
+/// The output looks like this
+///
+/// ```text
+/// Scope                                        Total       Self      Count
+/// Frame                                     76.50 µs   40.60 µs         10
+/// ├─ Draw 0                                  6.34 µs    6.34 µs          9
+/// ├─ Draw 1                                  5.11 µs    5.11 µs          8
+/// ├─ Draw 2                                  4.21 µs    4.21 µs          7
+/// ├─ Draw 3                                  3.54 µs    3.54 µs          6
+/// ├─ Draw 4                                  3.04 µs    3.04 µs          5
+/// └─ 4 more                                  5.66 µs    5.66 µs         10
+///
+/// Counter                                        Min        Max       Last
+/// Frames : drawn                                   0          9          9
+/// ```
+pub struct ConsoleWriter {
+    console: Console,
+    top: Option<usize>,
+    width: Option<usize>,
+    color: Option<bool>,
+    // see report::weights
+    weighted: bool,
+}
+
+impl ConsoleWriter {
+    /// Constructs the writer that prints to `console`
+    pub fn new(console: Console) -> ConsoleWriter {
+        ConsoleWriter {
+            console,
+            top: None,
+            width: None,
+            color: None,
+            weighted: false,
+        }
+    }
+
+    /// Prints only the `count` longest scopes of every level
+    ///
+    /// The rest of the scopes are summed up in one line.
+    pub fn top(mut self, count: usize) -> ConsoleWriter {
+        self.top = Some(count);
+        self
+    }
+
+    /// Cuts the lines to `width` characters instead of the
+    /// width of the terminal
+    pub fn width(mut self, width: usize) -> ConsoleWriter {
+        self.width = Some(width);
+        self
+    }
+
+    /// Turns the colors on or off instead of detecting the terminal
+    pub fn color(mut self, color: bool) -> ConsoleWriter {
+        self.color = Some(color);
+        self
+    }
+
+    /// Counts the sampled scopes as many times as their "weight" args
+    ///
+    /// See [Sampling].
+    ///
+    /// [Sampling]: enum.Sampling.html
+    pub fn weighted(mut self, weighted: bool) -> ConsoleWriter {
+        self.weighted = weighted;
+        self
+    }
+
+    fn line_width(&self) -> usize {
+        self.width
+            .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+            .unwrap_or(DEFAULT_WIDTH)
+    }
+
+    fn colored(&self) -> bool {
+        self.color.unwrap_or_else(|| {
+            let terminal = match self.console {
+                Console::Stdout => io::stdout().is_terminal(),
+                Console::Stderr => io::stderr().is_terminal(),
+            };
+            terminal && env::var_os("NO_COLOR").is_none()
+        })
+    }
+}
+
+// Formatter of the lines of the summary
+struct Summary {
+    out: String,
+    // width of the name column
+    name: usize,
+    // number of columns of numbers that fit in the line
+    columns: usize,
+    // columns of numbers printed in the current line
+    printed: usize,
+    color: bool,
+}
+
+impl Summary {
+    fn paint(&mut self, color: &str, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        if self.color && !color.is_empty() {
+            write!(self.out, "{}{}{}", color, text, RESET).unwrap();
+        } else {
+            self.out.push_str(text);
+        }
+    }
+
+    // prints `prefix` and `name` cut to the name column
+    fn name(&mut self, prefix: &str, name: &str, color: &str) {
+        let prefix_len = prefix.chars().count();
+        let space = self.name.saturating_sub(prefix_len);
+        let len = name.chars().count();
+
+        let name = if len <= space {
+            format!("{:1$}", name, space)
+        } else if space > 0 {
+            let mut name = name.chars().take(space - 1).collect::<String>();
+            name.push('…');
+            name
+        } else {
+            String::new()
+        };
+
+        self.paint(DIM, &prefix.chars().take(self.name).collect::<String>());
+        self.paint(color, &name);
+    }
+
+    fn column(&mut self, text: &str, color: &str) {
+        if self.printed < self.columns {
+            self.printed += 1;
+            self.paint(color, &format!("{:>1$}", text, COLUMN));
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.printed = 0;
+        self.out.push('\n');
+    }
+
+    fn header(&mut self, columns: &[&str]) {
+        let name = columns[0].chars().take(self.name).collect::<String>();
+        let mut header = format!("{:1$}", name, self.name);
+        for column in columns[1..].iter().take(self.columns) {
+            write!(header, "{:>1$}", column, COLUMN).unwrap();
+        }
+        self.paint(BOLD, &header);
+        self.out.push('\n');
+    }
+}
+
+// times of the nodes without the overhead of the nested scopes,
+// the total is the sum of the corrected times of the nested scopes
+// so it is never less than them
+fn corrected(tree: &[Node], overhead: f32) -> Vec<(f32, f32)> {
+    let mut times = vec![(0.0, 0.0); tree.len()];
+
+    // the children come after their parents, so they are corrected first
+    for (index, node) in tree.iter().enumerate().rev() {
+        let mut total = 0.0;
+        let mut children = 0;
+        for &child in node.children.iter() {
+            total += times[child].0;
+            children += tree[child].count;
+        }
+
+        let self_time = (node.self_time - overhead * children as f32).max(0.0);
+        times[index] = (total + self_time, self_time);
+    }
+
+    times
+}
+
+fn time_color(time: f32, all: f32) -> &'static str {
+    if time >= all * 0.5 {
+        RED
+    } else if time >= all * 0.1 {
+        YELLOW
+    } else {
+        ""
+    }
+}
+
+// Line of the tree that is left to print
+enum Line {
+    // node, prefix of the line, depth and whether it is the last child
+    Node(usize, String, usize, bool),
+    // children of the node that are not shown, prefix and depth
+    Rest(usize, String, usize),
+}
+
+struct Tree<'a> {
+    nodes: Vec<Node<'a>>,
+    // corrected total and self times of the nodes
+    times: Vec<(f32, f32)>,
+    top: Option<usize>,
+    // total time of the outermost scopes, the self
+    // times are colored by their part of it
+    all: f32,
+}
+
+impl<'a> Tree<'a> {
+    // pushes the lines of the children of `node` so
+    // that they are popped in the order of printing
+    fn push_children(&self, lines: &mut Vec<Line>, node: usize, prefix: &str, depth: usize) {
+        let children = &self.nodes[node].children;
+        let shown = self.top.unwrap_or(children.len()).min(children.len());
+
+        if shown < children.len() {
+            lines.push(Line::Rest(node, prefix.to_string(), depth));
+        }
+        for (i, &child) in children[..shown].iter().enumerate().rev() {
+            let last = i + 1 == children.len();
+            lines.push(Line::Node(child, prefix.to_string(), depth, last));
+        }
+    }
+
+    fn print(&self, summary: &mut Summary) {
+        let mut lines = Vec::new();
+        self.push_children(&mut lines, 0, "", 0);
+
+        while let Some(line) = lines.pop() {
+            match line {
+                Line::Node(node, prefix, depth, last) => {
+                    let (branch, indent) = match (depth, last) {
+                        (0, _) => ("", ""),
+                        (_, false) => ("├─ ", "│  "),
+                        (_, true) => ("└─ ", "   "),
+                    };
+
+                    let (total, self_time) = self.times[node];
+                    let color = if depth == 0 { BOLD } else { "" };
+
+                    summary.name(
+                        &format!("{}{}", prefix, branch),
+                        self.nodes[node].name,
+                        color,
+                    );
+                    summary.column(&format_time(total), "");
+                    summary.column(&format_time(self_time), time_color(self_time, self.all));
+                    summary.column(&self.nodes[node].count.to_string(), "");
+                    summary.end_line();
+
+                    let prefix = format!("{}{}", prefix, indent);
+                    self.push_children(&mut lines, node, &prefix, depth + 1);
+                }
+                Line::Rest(node, prefix, depth) => {
+                    // the rest are summed up
+                    let children = &self.nodes[node].children;
+                    let rest = &children[self.top.unwrap_or(children.len())..];
+
+                    let (mut total, mut self_time, mut count) = (0.0, 0.0, 0);
+                    for &child in rest {
+                        total += self.times[child].0;
+                        self_time += self.times[child].1;
+                        count += self.nodes[child].count;
+                    }
+
+                    let branch = if depth == 0 { "" } else { "└─ " };
+                    let more = format!("{} more", rest.len());
+                    summary.name(&format!("{}{}", prefix, branch), &more, DIM);
+                    summary.column(&format_time(total), "");
+                    summary.column(&format_time(self_time), "");
+                    summary.column(&count.to_string(), "");
+                    summary.end_line();
+                }
+            }
+        }
+    }
+}
+
+// minimum, maximum and last value of every counter field
+fn counters(data: &[BenchData]) -> BTreeMap<String, (f32, f32, f32)> {
+    let mut counters = BTreeMap::new();
+    for data in data {
+        if let BenchData::Count { name, data, .. } = data {
+            for (field, value) in data {
+                let counter = counters
+                    .entry(format!("{} : {}", name, field))
+                    .or_insert((*value, *value, *value));
+                counter.0 = counter.0.min(*value);
+                counter.1 = counter.1.max(*value);
+                counter.2 = *value;
+            }
+        }
+    }
+    counters
+}
+
+fn format_value(value: f32) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e9 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+impl ConsoleWriter {
+    fn summary(&self, data: &[BenchData]) -> String {
+        let overhead = overhead(data);
+        let nodes = scope_tree(data, self.weighted);
+        let tree = Tree {
+            all: nodes[0].total,
+            times: corrected(&nodes, overhead),
+            nodes,
+            top: self.top,
+        };
+        let counters = counters(data);
+
+        let width = self.line_width();
+        let columns = (width.saturating_sub(MIN_NAME) / COLUMN).min(3);
+        let mut summary = Summary {
+            out: String::new(),
+            name: width - columns * COLUMN,
+            columns,
+            printed: 0,
+            color: self.colored(),
+        };
+
+        if tree.nodes[0].children.is_empty() && counters.is_empty() {
+            return summary.out;
+        }
+
+        if !tree.nodes[0].children.is_empty() {
+            summary.header(&["Scope", "Total", "Self", "Count"]);
+            tree.print(&mut summary);
+        }
+
+        if !counters.is_empty() {
+            if !summary.out.is_empty() {
+                summary.out.push('\n');
+            }
+
+            summary.header(&["Counter", "Min", "Max", "Last"]);
+            for (name, (min, max, last)) in counters {
+                summary.name("", &name, "");
+                summary.column(&format_value(min), "");
+                summary.column(&format_value(max), "");
+                summary.column(&format_value(last), "");
+                summary.end_line();
+            }
+        }
+
+        summary.out
+    }
+}
+
+impl Writer for ConsoleWriter {
+    fn end(&self, data: &[BenchData]) {
+        let summary = self.summary(data);
+
+        // the summary is printed at once, so it is not
+        // mixed with the output of other threads
+        match self.console {
+            Console::Stdout => io::stdout().lock().write_all(summary.as_bytes()).unwrap(),
+            Console::Stderr => io::stderr().lock().write_all(summary.as_bytes()).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn scope(name: &'static str, id: u64, parent: Option<u64>, dur: f32) -> BenchData {
+        BenchData::Bench {
+            name: Cow::Borrowed(name),
+            cat: Cow::Borrowed("function"),
+            ts: 0.0,
+            dur,
+            tid: 0,
+            args: Vec::new(),
+            id,
+            parent,
+            depth: parent.map_or(0, |_| 1),
+        }
+    }
+
+    // "Frame" with the children that take `durs`
+    fn frame(children: &[(&'static str, f32)]) -> Vec<BenchData> {
+        let mut data = children
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, dur))| scope(name, i as u64 + 1, Some(0), dur))
+            .collect::<Vec<_>>();
+        data.push(scope("Frame", 0, None, 100.0));
+        data
+    }
+
+    fn summary(writer: ConsoleWriter, data: &[BenchData]) -> Vec<String> {
+        let summary = writer.color(false).summary(data);
+        summary.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn long_names() {
+        let data = frame(&[("A very long scope name", 10.0)]);
+        let lines = summary(ConsoleWriter::new(Console::Stdout).width(44), &data);
+
+        // the name column is 11 characters wide
+        assert!(lines[2].starts_with("└─ A very …"));
+        assert!(lines.iter().all(|line| line.chars().count() == 44));
+    }
+
+    #[test]
+    fn short_names() {
+        let data = frame(&[("A", 10.0)]);
+        let lines = summary(ConsoleWriter::new(Console::Stdout).width(44), &data);
+
+        assert!(lines[2].starts_with("└─ A        "));
+        assert!(lines.iter().all(|line| line.chars().count() == 44));
+    }
+
+    #[test]
+    fn narrow_lines() {
+        let data = frame(&[("A very long scope name", 10.0)]);
+
+        // only the total column fits next to the name
+        let lines = summary(ConsoleWriter::new(Console::Stdout).width(20), &data);
+        assert_eq!(lines[0], "Scope          Total");
+        assert!(lines.iter().all(|line| line.chars().count() == 20));
+
+        // no columns fit
+        let lines = summary(ConsoleWriter::new(Console::Stdout).width(4), &data);
+        assert!(lines.iter().all(|line| line.chars().count() <= 4));
+    }
+
+    #[test]
+    fn top_larger_than_scopes() {
+        let data = frame(&[("A", 30.0), ("B", 20.0), ("C", 10.0)]);
+        let lines = summary(ConsoleWriter::new(Console::Stdout).width(80).top(10), &data);
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| !line.contains("more")));
+        assert!(lines[4].starts_with("└─ C "));
+    }
+
+    #[test]
+    fn ties() {
+        let data = frame(&[("A", 20.0), ("B", 20.0), ("C", 20.0)]);
+        let writer = || ConsoleWriter::new(Console::Stdout).width(80).top(1);
+        let lines = summary(writer(), &data);
+
+        // the scopes with the same times keep the order of the trace
+        assert!(lines[2].starts_with("├─ A "));
+        assert!(lines[3].starts_with("└─ 2 more "));
+        assert_eq!(lines, summary(writer(), &data));
+    }
+}
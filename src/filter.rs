@@ -0,0 +1,278 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Mutex, Once, RwLock};
+
+// the filter that is currently used, None if everything is collected
+static FILTER: RwLock<Option<Filter>> = RwLock::new(None);
+static FILTER_SET: AtomicBool = AtomicBool::new(false);
+
+// callsites that have cached their decision
+static CALLSITES: Mutex<Vec<&'static Callsite>> = Mutex::new(Vec::new());
+
+// completed when the filter is set, so GBENCH_FILTER
+// is read at most once and only if it is not set
+static FILTER_CHOSEN: Once = Once::new();
+
+// environment variable with the filter
+const FILTER_ENV: &str = "GBENCH_FILTER";
+
+// matches `text` against a glob pattern with `*` and `?` wildcards
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it matched up to
+    let mut star = None;
+
+    while t < text.len() {
+        // `*` is a wildcard even if the text has `*` at this place
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Filter of the collected events by name and category
+///
+/// Filter is made of comma separated glob patterns, where `*` matches
+/// any sequence of characters and `?` matches any character. A pattern
+/// prefixed with `-` excludes the events that match it. An event is
+/// collected if its name or category matches any of the including
+/// patterns (or there are none) and does not match any of the
+/// excluding patterns.
+///
+/// The events are matched by the names as they are written in the
+/// code, before the arguments are formatted. Scopes are matched by
+/// the format string given to [scope!], logs by the format string
+/// given to [log!] and counters by their names, so the decision is
+/// made once for every place in the code. Scopes started with
+/// [TimeScope::new] or [Collector::scope] are matched by the name
+/// given to them, which is the formatted name if it was formatted
+/// by the caller. Categories of scopes, logs and counters are
+/// "function", "log" and "count".
+///
+/// The filter is set with [set_filter] or with `GBENCH_FILTER`
+/// environment variable, which is read once when the first collector
+/// is constructed if [set_filter] was not called before.
+///
+/// [scope!]: macro.scope.html
+/// [log!]: macro.log.html
+/// [set_filter]: fn.set_filter.html
+/// [TimeScope::new]: struct.TimeScope.html#method.new
+/// [Collector::scope]: struct.Collector.html#method.scope
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::Filter;
+///
+/// let filter = Filter::parse("net::*,-net::poll");
+///
+/// assert!(filter.enabled("net::read", "function"));
+/// assert!(!filter.enabled("net::poll", "function"));
+/// assert!(!filter.enabled("db::query", "function"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Filter {
+    /// Parses the filter from comma separated patterns
+    pub fn parse(filter: &str) -> Filter {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+
+        for pattern in filter.split(',').map(str::trim) {
+            if let Some(pattern) = pattern.strip_prefix('-') {
+                exclude.push(String::from(pattern.trim()));
+            } else if !pattern.is_empty() {
+                include.push(String::from(pattern));
+            }
+        }
+
+        Filter { include, exclude }
+    }
+
+    /// Returns true if an event with this name and category is collected
+    pub fn enabled(&self, name: &str, cat: &str) -> bool {
+        let matches = |pattern: &String| glob_match(pattern, name) || glob_match(pattern, cat);
+
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Sets the filter of the collected events
+///
+/// `None` removes the filter, so all the events are collected. The
+/// filter is kept when collectors are constructed later, even if
+/// `GBENCH_FILTER` environment variable is present.
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, set_filter, ChromeTracing, Filter};
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///     set_filter(Some(Filter::parse("-Ignored")));
+///
+///     for _ in 0..1000 {
+///         // This scope is not collected
+///         scope!(sc | "Ignored");
+///     }
+///
+///     set_filter(None);
+/// }
+/// ```
+pub fn set_filter(filter: Option<Filter>) {
+    // GBENCH_FILTER does not replace this filter
+    FILTER_CHOSEN.call_once(|| {});
+    store_filter(filter);
+}
+
+fn store_filter(filter: Option<Filter>) {
+    let mut lock = FILTER.write().unwrap();
+    FILTER_SET.store(filter.is_some(), Ordering::SeqCst);
+    *lock = filter;
+
+    // callsites decide again with the new filter
+    for callsite in CALLSITES.lock().unwrap().iter() {
+        callsite.state.store(UNKNOWN, Ordering::SeqCst);
+    }
+}
+
+// sets the filter from GBENCH_FILTER if it is present,
+// only the first time and if the filter was not set
+pub fn filter_from_env() {
+    FILTER_CHOSEN.call_once(|| {
+        if let Ok(filter) = env::var(FILTER_ENV) {
+            store_filter(Some(Filter::parse(&filter)));
+        }
+    });
+}
+
+// checks the current filter
+pub fn enabled(name: &str, cat: &str) -> bool {
+    if !FILTER_SET.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    match &*FILTER.read().unwrap() {
+        Some(filter) => filter.enabled(name, cat),
+        None => true,
+    }
+}
+
+const UNKNOWN: u8 = 0;
+const ENABLED: u8 = 1;
+const DISABLED: u8 = 2;
+
+// Place in the code where an event is produced,
+// caches the filter decision for the event
+#[doc(hidden)]
+pub struct Callsite {
+    name: &'static str,
+    cat: &'static str,
+    state: AtomicU8,
+    registered: AtomicBool,
+}
+
+impl Callsite {
+    pub const fn new(name: &'static str, cat: &'static str) -> Callsite {
+        Callsite {
+            name,
+            cat,
+            state: AtomicU8::new(UNKNOWN),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&'static self) -> bool {
+        match self.state.load(Ordering::Relaxed) {
+            ENABLED => true,
+            DISABLED => false,
+            _ => self.decide(),
+        }
+    }
+
+    #[cold]
+    fn decide(&'static self) -> bool {
+        if !self.registered.swap(true, Ordering::SeqCst) {
+            CALLSITES.lock().unwrap().push(self);
+        }
+
+        // the state is stored under the lock so set_filter
+        // can not reset it before it is stored
+        let lock = FILTER.read().unwrap();
+        let enabled = match &*lock {
+            Some(filter) => filter.enabled(self.name, self.cat),
+            None => true,
+        };
+        self.state
+            .store(if enabled { ENABLED } else { DISABLED }, Ordering::SeqCst);
+
+        enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        assert!(glob_match("net::read", "net::read"));
+        assert!(!glob_match("net::read", "net::reads"));
+        assert!(!glob_match("net::read", "net::rea"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("a?c", "a?c"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("net::*", "net::"));
+        assert!(glob_match("net::*", "net::read"));
+        assert!(glob_match("*::read", "net::read"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("a**c", "abc"));
+        assert!(!glob_match("a*b", "aXbY"));
+        assert!(!glob_match("net::*", "db::read"));
+    }
+
+    #[test]
+    fn star_in_text() {
+        assert!(glob_match("op*", "op*x"));
+        assert!(glob_match("op*x", "op*x"));
+        assert!(glob_match("*x", "op*x"));
+        assert!(glob_match("op*", "op*"));
+        assert!(!glob_match("op*y", "op*x"));
+        assert!(!Filter::parse("-op*").enabled("op*x", "function"));
+    }
+}
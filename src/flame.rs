@@ -0,0 +1,274 @@
+use std::io::Write;
+
+use crate::global::BenchData;
+use crate::report::{args_text, escape, fit, format_time, hue, scope_tree, time_range, Node};
+use crate::view::TraceView;
+use crate::writer::{Output, Writer};
+
+// width of the chart in svg units
+const WIDTH: f32 = 1200.0;
+// height of a row of scopes
+const ROW: f32 = 18.0;
+// height of the title of a thread
+const HEADER: f32 = 20.0;
+
+/// View of the scopes drawn by [FlameChartWriter]
+///
+/// [FlameChartWriter]: struct.FlameChartWriter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlameChart {
+    /// Scopes of every thread in the order of time, with the
+    /// outermost scopes at the bottom
+    Timeline,
+    /// Scopes of all threads with the same stack of names merged,
+    /// with the outermost scopes at the top and the widths
+    /// proportional to the total durations
+    Icicle,
+}
+
+/// Writer for svg flame charts
+///
+/// The chart is a standalone svg image that can be embedded in
+/// markdown or opened in a browser. Hovering over a scope shows
+/// its name, duration and args.
+///
+/// The output files are saved to the folder given to [new], which
+/// is created if it does not exist.
+///
+/// [new]: struct.FlameChartWriter.html#method.new
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, FlameChart, FlameChartWriter, Tee};
+///
+/// fn main() {
+///     // Draws both views
+///     instantiate!(Tee(
+///         FlameChartWriter::new("target/bench", FlameChart::Timeline),
+///         FlameChartWriter::new("target/bench", FlameChart::Icicle)
+///     ));
+///
+///     for i in 0..3 {
+///         scope!(frame | "Frame {}", i);
+///         scope!(draw | "Draw");
+///     }
+/// }
+/// ```
+pub struct FlameChartWriter {
+    folder: &'static str,
+    chart: FlameChart,
+    // see report::weights
+    weighted: bool,
+}
+
+impl FlameChartWriter {
+    /// Constructs the writer that draws the [FlameChart] view `chart`
+    /// to `folder`
+    ///
+    /// [FlameChart]: enum.FlameChart.html
+    pub fn new(folder: &'static str, chart: FlameChart) -> FlameChartWriter {
+        FlameChartWriter {
+            folder,
+            chart,
+            weighted: false,
+        }
+    }
+
+    /// Counts the sampled scopes in the icicle view as many times as
+    /// their "weight" args
+    ///
+    /// The timeline view always shows the collected scopes. See
+    /// [Sampling].
+    ///
+    /// [Sampling]: enum.Sampling.html
+    pub fn weighted(mut self, weighted: bool) -> FlameChartWriter {
+        self.weighted = weighted;
+        self
+    }
+}
+
+fn write_header(file: &mut impl Write, height: f32) {
+    writeln!(file, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(
+        file,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        WIDTH, height, WIDTH, height
+    )
+    .unwrap();
+    writeln!(
+        file,
+        "<style>text {{ font: 11px sans-serif; pointer-events: none; }} \
+         rect.s:hover {{ stroke: #000; }}</style>"
+    )
+    .unwrap();
+    writeln!(file, "<rect width=\"100%\" height=\"100%\" fill=\"#fff\"/>").unwrap();
+}
+
+fn write_title(file: &mut impl Write, title: &str, y: f32) {
+    writeln!(
+        file,
+        "<text x=\"4\" y=\"{:.2}\" style=\"font-weight: bold\">{}</text>",
+        y + HEADER - 6.0,
+        escape(title)
+    )
+    .unwrap();
+}
+
+// rectangle of a scope with the tooltip
+fn write_frame(file: &mut impl Write, name: &str, tooltip: &str, x: f32, y: f32, width: f32) {
+    let width = width.max(0.1);
+
+    write!(
+        file,
+        "<g><title>{}</title><rect class=\"s\" x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" \
+         height=\"{:.2}\" fill=\"hsl({}, 70%, 65%)\" stroke=\"#fff\" stroke-width=\"0.5\"/>",
+        escape(tooltip),
+        x,
+        y,
+        width,
+        ROW - 1.0,
+        hue(name)
+    )
+    .unwrap();
+    if let Some(label) = fit(name, width) {
+        write!(
+            file,
+            "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>",
+            x + 2.0,
+            y + ROW - 5.0,
+            escape(&label)
+        )
+        .unwrap();
+    }
+    writeln!(file, "</g>").unwrap();
+}
+
+// number of rows of the scopes of every thread
+fn thread_rows(view: &TraceView) -> Vec<(usize, usize)> {
+    view.threads()
+        .into_iter()
+        .filter_map(|tid| {
+            view.thread(tid)
+                .into_iter()
+                .filter_map(|data| match data {
+                    BenchData::Bench { depth, .. } => Some(depth + 1),
+                    _ => None,
+                })
+                .max()
+                .map(|rows| (tid, rows))
+        })
+        .collect()
+}
+
+fn write_timeline(file: &mut impl Write, data: &[BenchData]) {
+    let view = TraceView::new(data);
+    let range = time_range(data);
+    let scale = WIDTH / (range.1 - range.0);
+
+    let threads = thread_rows(&view);
+    let height = threads
+        .iter()
+        .map(|(_, rows)| HEADER + *rows as f32 * ROW)
+        .sum::<f32>()
+        .max(HEADER);
+
+    write_header(file, height);
+
+    let mut top = 0.0;
+    for (tid, rows) in threads {
+        write_title(file, &format!("Thread {}", tid), top);
+        top += HEADER;
+
+        for data in view.thread(tid) {
+            if let BenchData::Bench {
+                name,
+                ts,
+                dur,
+                args,
+                depth,
+                ..
+            } = data
+            {
+                let mut tooltip = format!("{}\n{}", name, format_time(*dur));
+                if !args.is_empty() {
+                    tooltip.push('\n');
+                    tooltip.push_str(&args_text(args));
+                }
+
+                let y = top + (rows - 1 - depth) as f32 * ROW;
+                write_frame(file, name, &tooltip, (ts - range.0) * scale, y, dur * scale);
+            }
+        }
+
+        top += rows as f32 * ROW;
+    }
+
+    writeln!(file, "</svg>").unwrap();
+}
+
+fn write_nodes(file: &mut impl Write, tree: &[Node], scale: f32) {
+    // left edges of the nodes, the children are laid out
+    // when their parent is drawn, which comes before them
+    let mut left = vec![0.0; tree.len()];
+
+    for (index, node) in tree.iter().enumerate() {
+        if index != 0 {
+            let tooltip = format!(
+                "{}\ntotal {} ({:.1}%)\nself {}\ncount {}",
+                node.name,
+                format_time(node.total),
+                node.total / tree[0].total * 100.0,
+                format_time(node.self_time),
+                node.count
+            );
+            let y = HEADER + (node.depth - 1) as f32 * ROW;
+            write_frame(
+                file,
+                node.name,
+                &tooltip,
+                left[index],
+                y,
+                node.total * scale,
+            );
+        }
+
+        let mut x = left[index];
+        for &child in node.children.iter() {
+            left[child] = x;
+            x += tree[child].total * scale;
+        }
+    }
+}
+
+fn write_icicle(file: &mut impl Write, data: &[BenchData], weighted: bool) {
+    let tree = scope_tree(data, weighted);
+    let rows = tree.iter().map(|node| node.depth).max().unwrap_or(0);
+    let height = HEADER + rows as f32 * ROW;
+
+    write_header(file, height);
+    write_title(file, "All threads", 0.0);
+
+    if tree[0].total > 0.0 {
+        let scale = WIDTH / tree[0].total;
+        write_nodes(file, &tree, scale);
+    }
+
+    writeln!(file, "</svg>").unwrap();
+}
+
+impl Writer for FlameChartWriter {
+    fn end(&self, data: &[BenchData]) {
+        let prefix = match self.chart {
+            FlameChart::Timeline => "flame",
+            FlameChart::Icicle => "icicle",
+        };
+
+        let mut file = Output::create(self.folder, prefix, "svg", None);
+        match self.chart {
+            FlameChart::Timeline => write_timeline(&mut file, data),
+            FlameChart::Icicle => write_icicle(&mut file, data, self.weighted),
+        }
+        file.finish();
+    }
+}
@@ -1,9 +1,11 @@
-use std::mem::{self, MaybeUninit};
-use std::sync::{Mutex, MutexGuard};
-use std::time::Instant;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{RwLock, TryLockError};
 
+use crate::collector::Collector;
 use crate::id::{IdBorrow, IdStorage};
-use crate::writer::Writer;
+use crate::panic;
 
 // Global data
 
@@ -12,94 +14,257 @@ use crate::writer::Writer;
 /// Data that is passed to the writers is in form of these enums.
 ///
 /// # Fields
+/// - **name** - name of the scope, borrowed if it is a literal
+/// - **cat** - category, "function", "log" and "count" by default
 /// - **ts** -  timestamp
 /// - **dur** - duration
 /// - **tid** - thread id
+/// - **args** - additional data attached to the event
+/// - **id** - span id that is unique for every scope
+/// - **parent** - span id of the scope this scope is nested in
+/// - **depth** - number of scopes this scope is nested in
 #[derive(Debug, Clone)]
 pub enum BenchData {
     /// Log contains logging data produced by the [log!](macro.log.html) macro
-    Log { log: String, ts: f32, tid: usize },
+    Log {
+        log: String,
+        cat: String,
+        ts: f32,
+        tid: usize,
+        args: Vec<(String, Arg)>,
+    },
 
     /// Bench contains benchmarking data produced by the [scope!](macro.scope.html) macro
     Bench {
-        name: String,
+        name: Cow<'static, str>,
+        cat: Cow<'static, str>,
         ts: f32,
         dur: f32,
         tid: usize,
+        args: Vec<(String, Arg)>,
+        id: u64,
+        parent: Option<u64>,
+        depth: usize,
     },
 
     /// Count contains counting data produced by the [count!](macro.count.html) macro
     Count {
         name: String,
+        cat: String,
         ts: f32,
         tid: usize,
         data: Vec<(String, f32)>,
     },
+
+    /// Meta contains information about the whole trace, such as
+    /// "scope overhead" which is the time in microseconds that one
+    /// scope adds to the duration of the scope it is nested in
+    Meta { name: String, value: Arg },
 }
 
-static mut GLOBAL_DATA: MaybeUninit<GlobalData> = MaybeUninit::uninit();
-struct GlobalData {
-    pub program_begin: Instant,
-    pub queue_mutex: Mutex<Vec<BenchData>>,
-    pub id_storage: IdStorage,
+/// Value of an argument attached to [BenchData]
+///
+/// [BenchData]: enum.BenchData.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
 }
 
-// method for event loop starting
-pub fn begin() {
-    // data initialization
-    let program_begin = Instant::now();
+// collector that is used when no collector is entered
+static DEFAULT: RwLock<Option<Collector>> = RwLock::new(None);
+static INSTANTIATED: AtomicBool = AtomicBool::new(false);
+static NEXT_SPAN: AtomicU64 = AtomicU64::new(0);
 
-    // file mutex
-    let queue_mutex = Mutex::new(Vec::new());
+// thread ids are shared by all the collectors
+static ID_STORAGE: IdStorage = IdStorage::new();
 
-    // id storage
-    let id_storage = IdStorage::new();
+thread_local! {
+    // collectors entered by the thread, the last one is current
+    static CURRENT: RefCell<Vec<Collector>> = const { RefCell::new(Vec::new()) };
+    static TID: IdBorrow = ID_STORAGE.gen();
+}
 
-    // writing data to global
-    unsafe {
-        GLOBAL_DATA = MaybeUninit::new(GlobalData {
-            queue_mutex,
-            program_begin,
-            id_storage,
-        });
+// scope that has started but has not ended yet
+pub struct OpenScope {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub depth: usize,
+    pub name: Cow<'static, str>,
+    pub cat: Cow<'static, str>,
+    pub start: f32,
+    pub tid: usize,
+}
+
+// sets the default collector
+pub fn set_default(collector: Option<Collector>) {
+    let mut lock = DEFAULT.write().unwrap();
+    INSTANTIATED.store(collector.is_some(), Ordering::SeqCst);
+    *lock = collector;
+}
+
+// removes the default collector if it is `collector`
+pub fn remove_default(collector: &Collector) {
+    let mut lock = DEFAULT.write().unwrap();
+    if lock
+        .as_ref()
+        .is_some_and(|default| default.ptr_eq(collector))
+    {
+        INSTANTIATED.store(false, Ordering::SeqCst);
+        *lock = None;
     }
 }
 
-// time of program beginning
-pub fn begin_time() -> Instant {
-    unsafe { (&*GLOBAL_DATA.as_ptr()).program_begin }
+// whether there is a collector for the current thread
+#[cfg(any(feature = "log", feature = "tracing"))]
+pub fn instantiated() -> bool {
+    INSTANTIATED.load(Ordering::SeqCst)
+        || CURRENT
+            .try_with(|current| !current.borrow().is_empty())
+            .unwrap_or(false)
 }
 
-// getting queue mutex
-pub fn queue_mutex() -> MutexGuard<'static, Vec<BenchData>> {
-    unsafe { (&*GLOBAL_DATA.as_ptr()).queue_mutex.lock().unwrap() }
+// collector of the current thread, the entered one or the default one
+pub fn current() -> Option<Collector> {
+    let entered = CURRENT
+        .try_with(|current| Some(current.try_borrow().ok()?.last()?.clone()))
+        .ok()
+        .flatten();
+
+    match entered {
+        Some(collector) => Some(collector),
+        None if INSTANTIATED.load(Ordering::Relaxed) => default(),
+        None => None,
+    }
 }
 
-// deinstantiate the gobal data
-pub fn end(writers: Vec<Box<dyn Writer + 'static>>) {
-    // get data to write
-    let data = {
-        let mut lock = queue_mutex();
-        mem::replace(&mut *lock, Vec::new())
-    };
+// default collector, the panic hook does not wait for the lock
+fn default() -> Option<Collector> {
+    if !panic::in_hook() {
+        return DEFAULT.read().unwrap().clone();
+    }
 
-    // write data to writers
-    for writer in writers {
-        writer.end(&data);
+    match DEFAULT.try_read() {
+        Ok(default) => default.clone(),
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().clone(),
+        Err(TryLockError::WouldBlock) => None,
     }
+}
 
-    // getting global data for dropping
-    unsafe {
-        let _gd = mem::replace(&mut GLOBAL_DATA, MaybeUninit::uninit()).assume_init();
+// makes `collector` current for the thread
+pub fn enter(collector: Collector) {
+    CURRENT.with(|current| current.borrow_mut().push(collector));
+}
+
+// removes the last entered `collector` of the thread, which
+// makes the previous collector current if it was the last one
+pub fn exit(collector: &Collector) {
+    let _ = CURRENT.try_with(|current| {
+        let mut current = current.borrow_mut();
+        if let Some(pos) = current
+            .iter()
+            .rposition(|entered| entered.ptr_eq(collector))
+        {
+            current.remove(pos);
+        }
+    });
+}
+
+// generates a unique span id
+pub fn next_span() -> u64 {
+    NEXT_SPAN.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Writes the collected data without ending the session
+///
+/// The data that is currently collected by the current [Collector]
+/// is given to its writers and the collection continues. This is
+/// mostly useful in flight recorder mode (see
+/// [Instantiator::flight_recorder]) to save the last events when
+/// something goes wrong. Does nothing if there is no current
+/// collector.
+///
+/// [Collector]: struct.Collector.html
+/// [Instantiator::flight_recorder]: struct.Instantiator.html#method.flight_recorder
+pub fn dump() {
+    if let Some(collector) = current() {
+        collector.dump();
     }
 }
 
-thread_local! {
-    static TID: IdBorrow = unsafe { (&mut *GLOBAL_DATA.as_mut_ptr()).id_storage.gen() };
+/// Returns a copy of the collected data without ending the session
+///
+/// The data is collected by the current [Collector] so far, with the
+/// [BenchData::Meta] first. The scopes that have not ended yet are not
+/// included. Returns an empty vector if there is no current collector.
+///
+/// [Collector]: struct.Collector.html
+/// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, snapshot, BenchData, ChromeTracing};
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///
+///     {
+///         scope!(sc | "Scope");
+///     }
+///
+///     let scopes = snapshot()
+///         .into_iter()
+///         .filter(|data| matches!(data, BenchData::Bench { .. }))
+///         .count();
+///     assert_eq!(scopes, 1);
+/// }
+/// ```
+pub fn snapshot() -> Vec<BenchData> {
+    match current() {
+        Some(collector) => collector.snapshot(),
+        None => Vec::new(),
+    }
 }
 
-pub fn gen_id() {
-    TID.with(|_| {});
+/// Takes the collected data out without ending the session
+///
+/// Works like [snapshot], but the returned events are removed, so
+/// they are not given to the writers and are not returned again. The
+/// [BenchData::Meta] is copied and kept.
+///
+/// [snapshot]: fn.snapshot.html
+/// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{drain, instantiate, scope, BenchData, ChromeTracing};
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///
+///     for frame in 0..3 {
+///         {
+///             scope!(sc | "Frame {}", frame);
+///         }
+///
+///         // Only the scope of this frame
+///         let scopes = drain()
+///             .into_iter()
+///             .filter(|data| matches!(data, BenchData::Bench { .. }))
+///             .count();
+///         assert_eq!(scopes, 1);
+///     }
+/// }
+/// ```
+pub fn drain() -> Vec<BenchData> {
+    match current() {
+        Some(collector) => collector.drain(),
+        None => Vec::new(),
+    }
 }
 
 pub fn get_id() -> usize {
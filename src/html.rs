@@ -0,0 +1,398 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::global::BenchData;
+use crate::report::{arg_text, args_text, escape, fit, format_time, hue, scope_stats, time_range};
+use crate::view::TraceView;
+use crate::writer::{Output, Writer};
+
+// width of the charts in svg units
+const WIDTH: f32 = 1000.0;
+// height of a row of scopes in the timeline
+const ROW: f32 = 18.0;
+// height of a counter chart
+const CHART: f32 = 120.0;
+
+const STYLE: &str = "\
+body { font: 13px sans-serif; margin: 24px; color: #222; }
+h1 { font-size: 20px; }
+h2 { font-size: 16px; margin-top: 32px; }
+h3 { font-size: 13px; margin: 16px 0 4px; }
+table { border-collapse: collapse; }
+th, td { padding: 2px 10px; border-bottom: 1px solid #ddd; }
+th { text-align: left; background: #f4f4f4; }
+td.n { text-align: right; font-variant-numeric: tabular-nums; }
+svg { display: block; width: 100%; background: #fafafa; border: 1px solid #ddd; }
+svg text { font: 11px sans-serif; pointer-events: none; }
+";
+
+/// Writer for a self-contained html report
+///
+/// The report is a single html file without external assets, so it
+/// can be opened in any browser or attached to a bug report. It has
+/// the summary table of the scopes, the timeline of the scopes of
+/// every thread, the charts of the counters and the logs. Hovering
+/// over a scope shows its duration and args. The "scope overhead"
+/// measured by the [Collector] is subtracted from the times in the
+/// summary table.
+///
+/// [Collector]: struct.Collector.html
+///
+/// The output files are saved to the folder given to [new], which
+/// is created if it does not exist.
+///
+/// [new]: struct.HtmlReportWriter.html#method.new
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{count, instantiate, log, scope, HtmlReportWriter};
+///
+/// fn main() {
+///     instantiate!(HtmlReportWriter::new("target/bench"));
+///
+///     for i in 0..10 {
+///         scope!(frame | "Frame {}", i % 2);
+///         log!("Frame {}", i);
+///         count!("Frames" => {"drawn" => i});
+///     }
+/// }
+/// ```
+pub struct HtmlReportWriter {
+    folder: &'static str,
+    // see report::weights
+    weighted: bool,
+}
+
+impl HtmlReportWriter {
+    /// Constructs the writer that saves the reports to `folder`
+    pub fn new(folder: &'static str) -> HtmlReportWriter {
+        HtmlReportWriter {
+            folder,
+            weighted: false,
+        }
+    }
+
+    /// Counts the sampled scopes in the summary as many times as
+    /// their "weight" args
+    ///
+    /// The timeline always shows the collected scopes. See [Sampling].
+    ///
+    /// [Sampling]: enum.Sampling.html
+    pub fn weighted(mut self, weighted: bool) -> HtmlReportWriter {
+        self.weighted = weighted;
+        self
+    }
+}
+
+fn write_meta(file: &mut impl Write, data: &[BenchData]) {
+    let meta = data
+        .iter()
+        .filter_map(|data| match data {
+            BenchData::Meta { name, value } => Some((name, value)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if meta.is_empty() {
+        return;
+    }
+
+    writeln!(file, "<table>").unwrap();
+    for (name, value) in meta {
+        writeln!(
+            file,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(name),
+            escape(&arg_text(value))
+        )
+        .unwrap();
+    }
+    writeln!(file, "</table>").unwrap();
+}
+
+fn write_summary(file: &mut impl Write, data: &[BenchData], weighted: bool) {
+    let stats = scope_stats(data, weighted, true);
+
+    writeln!(file, "<h2>Scopes</h2>").unwrap();
+    if stats.is_empty() {
+        writeln!(file, "<p>No scopes</p>").unwrap();
+        return;
+    }
+
+    writeln!(
+        file,
+        "<table><tr><th>Name</th><th>Count</th><th>Total</th><th>Self</th>\
+         <th>Mean</th><th>Min</th><th>Max</th></tr>"
+    )
+    .unwrap();
+    for stats in stats {
+        writeln!(
+            file,
+            "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+             <td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+             <td class=\"n\">{}</td></tr>",
+            escape(stats.name),
+            stats.count,
+            format_time(stats.total),
+            format_time(stats.self_time),
+            format_time(stats.total / stats.count as f32),
+            format_time(stats.min),
+            format_time(stats.max)
+        )
+        .unwrap();
+    }
+    writeln!(file, "</table>").unwrap();
+}
+
+fn write_timeline(file: &mut impl Write, view: &TraceView, tid: usize, range: (f32, f32)) {
+    let scale = WIDTH / (range.1 - range.0);
+    let events = view.thread(tid);
+
+    let rows = events
+        .iter()
+        .filter_map(|data| match data {
+            BenchData::Bench { depth, .. } => Some(depth + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(1);
+    let height = rows as f32 * ROW;
+
+    writeln!(file, "<h3>Thread {}</h3>", tid).unwrap();
+    writeln!(file, "<svg viewBox=\"0 0 {} {}\">", WIDTH, height).unwrap();
+
+    for data in events {
+        match data {
+            BenchData::Bench {
+                name,
+                ts,
+                dur,
+                args,
+                depth,
+                ..
+            } => {
+                let x = (ts - range.0) * scale;
+                let width = (dur * scale).max(0.1);
+                let y = *depth as f32 * ROW;
+
+                write!(file, "<g><title>{}\n{}", escape(name), format_time(*dur)).unwrap();
+                if !args.is_empty() {
+                    write!(file, "\n{}", escape(&args_text(args))).unwrap();
+                }
+                write!(
+                    file,
+                    "</title><rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" \
+                     fill=\"hsl({}, 55%, 70%)\" stroke=\"#fff\" stroke-width=\"0.5\"/>",
+                    x,
+                    y,
+                    width,
+                    ROW - 1.0,
+                    hue(name)
+                )
+                .unwrap();
+                if let Some(label) = fit(name, width) {
+                    write!(
+                        file,
+                        "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>",
+                        x + 2.0,
+                        y + ROW - 5.0,
+                        escape(&label)
+                    )
+                    .unwrap();
+                }
+                writeln!(file, "</g>").unwrap();
+            }
+            BenchData::Log { log, ts, .. } => {
+                let x = (ts - range.0) * scale;
+                writeln!(
+                    file,
+                    "<g><title>{}</title><line x1=\"{:.2}\" y1=\"0\" x2=\"{:.2}\" y2=\"{:.2}\" \
+                     stroke=\"#c33\" stroke-width=\"1\"/></g>",
+                    escape(log),
+                    x,
+                    x,
+                    height
+                )
+                .unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    writeln!(file, "</svg>").unwrap();
+}
+
+// values of every field of every counter
+type Series<'a> = BTreeMap<&'a str, BTreeMap<&'a str, Vec<(f32, f32)>>>;
+
+fn write_chart(
+    file: &mut impl Write,
+    name: &str,
+    fields: &BTreeMap<&str, Vec<(f32, f32)>>,
+    range: (f32, f32),
+) {
+    let scale = WIDTH / (range.1 - range.0);
+
+    let values = fields.values().flatten().map(|(_, value)| *value);
+    let (mut min, mut max) = values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    if min == max {
+        min -= 1.0;
+        max += 1.0;
+    }
+    let y = |value: f32| 4.0 + (max - value) / (max - min) * (CHART - 8.0);
+
+    writeln!(file, "<h3>{}</h3>", escape(name)).unwrap();
+    writeln!(file, "<svg viewBox=\"0 0 {} {}\">", WIDTH, CHART).unwrap();
+
+    for (field, values) in fields {
+        // the counter keeps its value until the next event
+        let mut points = String::new();
+        let mut last = None;
+        for (ts, value) in values {
+            let x = (ts - range.0) * scale;
+            if let Some(last) = last {
+                points.push_str(&format!("{:.2},{:.2} ", x, y(last)));
+            }
+            points.push_str(&format!("{:.2},{:.2} ", x, y(*value)));
+            last = Some(*value);
+        }
+
+        writeln!(
+            file,
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"hsl({}, 65%, 45%)\" \
+             stroke-width=\"1.5\"><title>{}</title></polyline>",
+            points.trim_end(),
+            hue(field),
+            escape(field)
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        file,
+        "<text x=\"2\" y=\"12\">{}</text><text x=\"2\" y=\"{}\">{}</text>",
+        max,
+        CHART - 3.0,
+        min
+    )
+    .unwrap();
+    writeln!(file, "</svg>").unwrap();
+
+    write!(file, "<p>").unwrap();
+    for (field, values) in fields {
+        let last = values.last().map(|(_, value)| *value).unwrap_or(0.0);
+        write!(
+            file,
+            "<span style=\"color: hsl({}, 65%, 45%)\">&#9632;</span> {} = {} ",
+            hue(field),
+            escape(field),
+            last
+        )
+        .unwrap();
+    }
+    writeln!(file, "</p>").unwrap();
+}
+
+fn write_counters(file: &mut impl Write, data: &[BenchData], range: (f32, f32)) {
+    let mut series = Series::new();
+    for data in data {
+        if let BenchData::Count { name, data, ts, .. } = data {
+            let fields = series.entry(name).or_default();
+            for (field, value) in data {
+                fields.entry(field).or_default().push((*ts, *value));
+            }
+        }
+    }
+    if series.is_empty() {
+        return;
+    }
+
+    writeln!(file, "<h2>Counters</h2>").unwrap();
+    for (name, fields) in series.iter_mut() {
+        for values in fields.values_mut() {
+            values.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+        write_chart(file, name, fields, range);
+    }
+}
+
+fn write_logs(file: &mut impl Write, view: &TraceView, range: (f32, f32)) {
+    let logs = view
+        .in_range(range.0, range.1)
+        .into_iter()
+        .filter(|data| matches!(data, BenchData::Log { .. }))
+        .collect::<Vec<_>>();
+    if logs.is_empty() {
+        return;
+    }
+
+    writeln!(file, "<h2>Logs</h2>").unwrap();
+    writeln!(
+        file,
+        "<table><tr><th>Time</th><th>Thread</th><th>Category</th><th>Message</th><th>Args</th></tr>"
+    )
+    .unwrap();
+    for data in logs {
+        if let BenchData::Log {
+            log,
+            cat,
+            ts,
+            tid,
+            args,
+        } = data
+        {
+            writeln!(
+                file,
+                "<tr><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                format_time(ts - range.0),
+                tid,
+                escape(cat),
+                escape(log),
+                escape(&args_text(args))
+            )
+            .unwrap();
+        }
+    }
+    writeln!(file, "</table>").unwrap();
+}
+
+fn write_html(file: &mut impl Write, data: &[BenchData], weighted: bool) {
+    let view = TraceView::new(data);
+    let range = time_range(data);
+
+    writeln!(file, "<!DOCTYPE html>").unwrap();
+    writeln!(
+        file,
+        "<html><head><meta charset=\"utf-8\"><title>gbench report</title>"
+    )
+    .unwrap();
+    writeln!(file, "<style>\n{}</style></head><body>", STYLE).unwrap();
+    writeln!(file, "<h1>gbench report</h1>").unwrap();
+
+    write_meta(file, data);
+    write_summary(file, data, weighted);
+
+    let threads = view.threads();
+    if !threads.is_empty() {
+        writeln!(file, "<h2>Timeline</h2>").unwrap();
+        writeln!(file, "<p>Total time {}</p>", format_time(range.1 - range.0)).unwrap();
+        for tid in threads {
+            write_timeline(file, &view, tid, range);
+        }
+    }
+
+    write_counters(file, data, range);
+    write_logs(file, &view, range);
+
+    writeln!(file, "</body></html>").unwrap();
+}
+
+impl Writer for HtmlReportWriter {
+    fn end(&self, data: &[BenchData]) {
+        let mut file = Output::create(self.folder, "report", "html", None);
+        write_html(&mut file, data, self.weighted);
+        file.finish();
+    }
+}
@@ -1,15 +1,13 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 
-type StorageInner = Arc<Mutex<Vec<bool>>>;
-
-pub struct IdStorage(StorageInner);
+pub struct IdStorage(Mutex<Vec<bool>>);
 
 impl IdStorage {
-    pub fn new() -> IdStorage {
-        IdStorage(Arc::new(Mutex::new(Vec::new())))
+    pub const fn new() -> IdStorage {
+        IdStorage(Mutex::new(Vec::new()))
     }
 
-    pub fn gen(&mut self) -> IdBorrow {
+    pub fn gen(&'static self) -> IdBorrow {
         let mut lock = self.0.lock().unwrap();
         let mut k = 0;
         while k < lock.len() && lock[k] {
@@ -23,14 +21,14 @@ impl IdStorage {
         }
 
         IdBorrow {
-            storage: self.0.clone(),
+            storage: self,
             id: k,
         }
     }
 }
 
 pub struct IdBorrow {
-    storage: StorageInner,
+    storage: &'static IdStorage,
     id: usize,
 }
 
@@ -42,7 +40,7 @@ impl IdBorrow {
 
 impl Drop for IdBorrow {
     fn drop(&mut self) {
-        let mut lock = self.storage.lock().unwrap();
+        let mut lock = self.storage.0.lock().unwrap();
         lock[self.id] = false;
     }
 }
@@ -8,6 +8,8 @@
 //! program running push this data in form of [BenchData] enums
 //! to a shared storage. At the end of the program all the data is given
 //! to the instances of [Writer] that are given at the initialization.
+//! The data that is collected so far can also be written at any moment
+//! with [dump], or read with [snapshot] and [drain].
 //!
 //! # Examples
 //!
@@ -78,6 +80,22 @@
 //! }
 //! ```
 //!
+//! Example of categories, which can be given to [scope!], [log!]
+//! and [count!] macros
+//! ```rust
+//! use gbench::{count, instantiate, log, scope, ChromeTracing};
+//!
+//! fn main() {
+//!     instantiate!(ChromeTracing("target/bench"));
+//!     for i in 0..10 {
+//!         // Chrome tracing can show only the events of some categories
+//!         scope!(read | cat = "io", "Reading file {}", i);
+//!         log!(cat = "io", "Opened file {}", i);
+//!         count!(cat = "io", "Files" => { "read" => i });
+//!     }
+//! }
+//! ```
+//!
 //! Full example
 //! ```rust
 //! use gbench::{instantiate, scope, ChromeTracing};
@@ -126,23 +144,108 @@
 //! }
 //! ```
 //!
+//! [scope!]: macro.scope.html
 //! [log!]: macro.log.html
 //! [count!]: macro.count.html
 //! [CsvWriter]: struct.CsvWriter.html
 //! [BenchData]: enum.BenchData.html
 //! [Writer]: trait.Writer.html
+//! [dump]: fn.dump.html
+//! [snapshot]: fn.snapshot.html
+//! [drain]: fn.drain.html
 
+mod alloc;
 mod bench;
+mod binary;
+mod clock;
+mod collector;
+mod console;
+mod filter;
+mod flame;
 mod global;
+mod html;
 mod id;
+mod live;
+#[cfg(feature = "log")]
+mod log_bridge;
+mod panic;
+mod process;
+mod queue;
+mod report;
+mod sample;
+mod sampler;
+#[cfg(feature = "tracing")]
+mod tracing_layer;
+mod view;
 mod writer;
 
+pub use alloc::AllocStats;
+pub use alloc::TrackingAllocator;
+
 pub use bench::Instantiator;
 pub use bench::TimeScope;
 
+pub use binary::write_binary;
+pub use binary::BinaryReader;
+pub use binary::BinaryWriter;
+
+pub use clock::Clock;
+#[cfg(target_os = "linux")]
+pub use clock::CoarseClock;
+pub use clock::InstantClock;
+pub use clock::ManualClock;
+#[cfg(target_arch = "x86_64")]
+pub use clock::TscClock;
+
+pub use collector::Collector;
+pub use collector::CollectorGuard;
+
+pub use console::Console;
+pub use console::ConsoleWriter;
+
+pub use filter::set_filter;
+pub use filter::Filter;
+
+pub use flame::FlameChart;
+pub use flame::FlameChartWriter;
+
+pub use global::drain;
+pub use global::dump;
+pub use global::snapshot;
+pub use global::Arg;
 pub use global::BenchData;
+
+pub use live::Endpoint;
+pub use live::LiveReader;
+
+pub use html::HtmlReportWriter;
+
+#[cfg(feature = "log")]
+pub use log_bridge::LogBridge;
+
+pub use panic::install_panic_hook;
+
+pub use queue::Window;
+
+pub use sample::Sampling;
+
+#[cfg(feature = "tracing")]
+pub use tracing_layer::TracingLayer;
+
+pub use view::TraceView;
+
+pub use writer::Batch;
 pub use writer::ChromeTracing;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use writer::Compressed;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use writer::Compression;
 pub use writer::CsvWriter;
+pub use writer::Filtered;
+pub use writer::Mapped;
+pub use writer::SessionInfo;
+pub use writer::StreamWriter;
+pub use writer::Tee;
 pub use writer::Writer;
 
 #[doc(hidden)]
@@ -151,29 +254,69 @@ pub use bench::_log;
 #[doc(hidden)]
 pub use bench::_count;
 
+#[doc(hidden)]
+pub use bench::_scope;
+
+#[doc(hidden)]
+pub use bench::_sampled_scope;
+
+#[doc(hidden)]
+pub use filter::Callsite as _Callsite;
+
+#[doc(hidden)]
+pub use sample::SampleSite as _SampleSite;
+
 /// Benchmarks a scope of code
 ///
 /// # Implementation
 ///
 /// The macro expands into a [TimeScope] declaration
 ///
-/// ```rust
+/// ```rust,ignore
 /// scope!(main)
 /// // expands into this
-/// let main = TimeScope::new(format!("main"));
+/// let main = TimeScope::new("main");
 /// ```
 ///
-/// ```rust
+/// ```rust,ignore
 /// scope!(main | "A {}", 0)
 /// // expands into this
 /// let main = TimeScope::new(format!("A {}", 0));
 /// ```
 ///
+/// The category of the scope, which is "function" by default, can be
+/// given before the name
+///
+/// ```rust,ignore
+/// scope!(main | cat = "io", "A {}", 0)
+/// // expands into this
+/// let main = TimeScope::with_category(format!("A {}", 0), String::from("io"));
+/// ```
+///
+/// The only difference is that the [Filter] is checked once for every
+/// place the macro is used, using the format string as the name. When
+/// the scope is filtered out the arguments are not evaluated and a
+/// disabled TimeScope is declared. Also the name is not allocated if
+/// it has no arguments, so a scope with a literal name does not allocate
+/// a String.
+///
+/// The scopes in hot code can be sampled, so only 1 of N executions
+/// is collected, by giving the [Sampling] rate before the category
+/// and the name. The collected scopes are marked with "weight" arg
+/// equal to N.
+///
+/// ```rust,ignore
+/// scope!(main | sample = 1000, "A {}", 0)
+/// scope!(main | sample = Sampling::Random(1000), cat = "io", "A {}", 0)
+/// ```
+///
 /// [TimeScope]: struct.TimeScope.html
+/// [Filter]: struct.Filter.html
+/// [Sampling]: enum.Sampling.html
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,ignore
 /// // You can organize your subtasks in scopes to
 /// // benchmark them
 /// scope!(imp | "An important task");
@@ -195,19 +338,60 @@ pub use bench::_count;
 ///
 /// // rest of the scope...
 /// ```
+///
+/// The macros can also be called by their paths
+///
+/// ```rust
+/// gbench::instantiate!(gbench::ChromeTracing("target/bench"));
+///
+/// gbench::scope!(sc | "Scope");
+/// gbench::log!("Log {}", 0);
+/// gbench::count!("Counter" => {"value" => 1});
+/// ```
 #[cfg(debug_assertions)]
 #[macro_export]
 macro_rules! scope {
     ($name:ident) => {
-        scope!($name | stringify!(name));
+        $crate::scope!($name | stringify!($name));
+    };
+
+    ($name:ident | cat = $cat:expr, $fmt:expr $(, $($arg:tt)*)?) => {
+        let $name = {
+            use $crate::{_scope, TimeScope, _Callsite};
+
+            static CALLSITE: _Callsite = _Callsite::new($fmt, $cat);
+
+            if CALLSITE.enabled() {
+                _scope(format_args!($fmt $(, $($arg)*)?), $cat)
+            } else {
+                TimeScope::disabled()
+            }
+        };
     };
 
-    ($name:ident | $($arg:tt)*) => {
+    ($name:ident | sample = $rate:expr, cat = $cat:expr, $fmt:expr $(, $($arg:tt)*)?) => {
         let $name = {
-            use gbench::TimeScope;
-            TimeScope::new(format!($($arg)*))
+            use $crate::{_sampled_scope, TimeScope, _Callsite, _SampleSite};
+
+            static CALLSITE: _Callsite = _Callsite::new($fmt, $cat);
+            static SAMPLE: _SampleSite = _SampleSite::new();
+
+            match SAMPLE.sample($rate) {
+                Some(weight) if CALLSITE.enabled() => {
+                    _sampled_scope(format_args!($fmt $(, $($arg)*)?), $cat, weight)
+                }
+                _ => TimeScope::disabled(),
+            }
         };
     };
+
+    ($name:ident | sample = $rate:expr, $fmt:expr $(, $($arg:tt)*)?) => {
+        $crate::scope!($name | sample = $rate, cat = "function", $fmt $(, $($arg)*)?);
+    };
+
+    ($name:ident | $fmt:expr $(, $($arg:tt)*)?) => {
+        $crate::scope!($name | cat = "function", $fmt $(, $($arg)*)?);
+    };
 }
 
 #[cfg(not(debug_assertions))]
@@ -227,7 +411,7 @@ macro_rules! scope {
 /// This macro expands into a declaration of [Instantiator] which instantiates
 /// global variables on creation and deinstantiates them on drop.
 ///
-/// ```rust
+/// ```rust,ignore
 /// instantiate!(ChromeTracing("target/bench"));
 /// // expands into this
 /// let __gbench_instantiator__ = Instantiator::new(vec![Box::new(ChromeTracing("target/bench"))]);
@@ -237,7 +421,7 @@ macro_rules! scope {
 /// of scope you can specify the variable name and then call [end] on it
 /// when you need the deinstantiation.
 ///
-/// ```rust
+/// ```rust,ignore
 /// instantiate!(ginst | ChromeTracing("target/bench"));
 /// // expands into this
 /// let ginst = Instantiator::new(vec![Box::new(ChromeTracing("target/bench"))]);
@@ -282,12 +466,12 @@ macro_rules! scope {
 macro_rules! instantiate {
     ($name: ident | $($writer: expr),*) => {
         let mut $name = {
-            use gbench::Instantiator;
+            use $crate::Instantiator;
 
             let mut writers = std::vec::Vec::new();
 
             $(
-                writers.push(std::boxed::Box::new($writer) as std::boxed::Box<dyn gbench::Writer + 'static>);
+                writers.push(std::boxed::Box::new($writer) as std::boxed::Box<dyn $crate::Writer + 'static>);
             )*
 
             Instantiator::new(writers)
@@ -295,7 +479,7 @@ macro_rules! instantiate {
     };
 
     ($($writer: expr),*) => {
-        instantiate!(__global_instantiator__ | $($writer),*);
+        $crate::instantiate!(__global_instantiator__ | $($writer),*);
     };
 }
 
@@ -307,30 +491,47 @@ macro_rules! instantiate {
 
 /// Logs data to a benchmarking file
 ///
-/// ```rust
+/// ```rust,ignore
 /// let a = 0;
 /// log!("A: {}", a);
 /// ```
 /// will queue this [BenchData]
-/// ```
+/// ```text
 /// Log {
 ///     log: "A: 0",
+///     cat: "log",
 ///     ts: /* event's timestamp */,
 ///     tid: /* event's thread of execution */,
+///     args: [],
 /// }
 /// ```
 ///
+/// The category, which is "log" by default, can be given
+/// before the format string
+/// ```rust,ignore
+/// log!(cat = "net", "Received {} bytes", n);
+/// ```
+///
 /// [BenchData]: enum.BenchData.html
 #[cfg(debug_assertions)]
 #[macro_export]
 macro_rules! log {
-    ($($arg:tt)*) => {
+    (cat = $cat:expr, $fmt:expr $(, $($arg:tt)*)?) => {
         {
-            use gbench::_log as log;
-            let log_string = format!($($arg)*);
-            log(log_string);
+            use $crate::{_log as log, _Callsite};
+
+            static CALLSITE: _Callsite = _Callsite::new($fmt, $cat);
+
+            if CALLSITE.enabled() {
+                let log_string = format!($fmt $(, $($arg)*)?);
+                log(log_string, std::string::String::from($cat));
+            }
         }
     };
+
+    ($fmt:expr $(, $($arg:tt)*)?) => {
+        $crate::log!(cat = "log", $fmt $(, $($arg)*)?)
+    };
 }
 
 #[cfg(not(debug_assertions))]
@@ -341,7 +542,7 @@ macro_rules! log {
 
 /// Creates a counting event
 ///
-/// ```rust
+/// ```rust,ignore
 /// let i = 10;
 /// count!(
 ///     "a" => {
@@ -356,9 +557,10 @@ macro_rules! log {
 ///
 /// Will queue these [BenchData]
 ///
-/// ```
+/// ```text
 /// Count {
 ///     name: "a",
+///     cat: "count",
 ///     ts: /* event's timestamp */,
 ///     tid: /* event's thread of execution */,
 ///     data: [
@@ -374,6 +576,7 @@ macro_rules! log {
 /// },
 /// Count {
 ///     name: "b",
+///     cat: "count",
 ///     ts: /* event's timestamp */,
 ///     tid: /* event's thread of execution */,
 ///     data: [
@@ -385,29 +588,49 @@ macro_rules! log {
 /// },
 /// ```
 ///
+/// The category, which is "count" by default, can be given
+/// before the counters
+/// ```rust,ignore
+/// count!(cat = "net", "Connections" => { "open" => n });
+/// ```
+///
+/// The [Filter] is checked once for every counter in every place
+/// the macro is used, so the names and the category have to be
+/// constant strings. The values of the filtered out counters are
+/// not evaluated.
+///
 /// [BenchData]: enum.BenchData.html
+/// [Filter]: struct.Filter.html
 #[cfg(debug_assertions)]
 #[macro_export]
 macro_rules! count {
-    ($($name: expr => {$($argname: expr => $val:expr),*}),*) => {{
-        use gbench::_count as count;
+    (cat = $cat:expr, $($name: expr => {$($argname: expr => $val:expr),*}),*) => {{
+        use $crate::{_count as count, _Callsite};
 
-        $(
-            let cname = std::string::String::from($name);
+        $({
+            static CALLSITE: _Callsite = _Callsite::new($name, $cat);
 
-            let mut data = std::vec::Vec::new();
+            if CALLSITE.enabled() {
+                let cname = std::string::String::from($name);
 
-            $(
-                data.push((std::string::String::from($argname), $val as f32));
-            )*
+                let mut data = std::vec::Vec::new();
+
+                $(
+                    data.push((std::string::String::from($argname), $val as f32));
+                )*
 
-            count(cname, data);
-        )*
+                count(cname, std::string::String::from($cat), data);
+            }
+        })*
     }};
+
+    ($($name: expr => {$($argname: expr => $val:expr),*}),*) => {
+        $crate::count!(cat = "count", $($name => {$($argname => $val),*}),*)
+    };
 }
 
 #[cfg(not(debug_assertions))]
 #[macro_export]
 macro_rules! count {
-    ($name: ident => {$($argname: ident => $val: expr),*}) => {};
+    ($($arg:tt)*) => {};
 }
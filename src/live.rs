@@ -0,0 +1,860 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::collector::{Collector, Store};
+use crate::global::{Arg, BenchData};
+use crate::writer::{write_arg, write_args, write_float, write_str, Event, Stream};
+
+// Live format
+//
+// Every event is a json object on its own line
+//
+// {"type":"log","log":..,"cat":..,"ts":..,"tid":..,"args":{..}}
+// {"type":"bench","name":..,"cat":..,"ts":..,"dur":..,"tid":..,"id":..,"parent":..,"depth":..,"args":{..}}
+// {"type":"count","name":..,"cat":..,"ts":..,"tid":..,"data":{..}}
+// {"type":"meta","name":..,"value":..}
+//
+// Float args always have a fraction or an exponent, so they can be
+// told from int args. Values that are not finite are null.
+
+// number of lines that are kept for a client that is not reading
+const BUFFER: usize = 4096;
+// how often the listener checks that the collection has not ended
+const POLL: Duration = Duration::from_millis(50);
+// time after which a client that is not reading is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// time the clients are given to read the last events at the end
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Address that the live stream is served on
+///
+/// See [Instantiator::listen].
+///
+/// [Instantiator::listen]: struct.Instantiator.html#method.listen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// TCP port on 127.0.0.1, port 0 picks a free port
+    Tcp(u16),
+    /// Unix domain socket at the path
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+// line of the live format with the event
+fn json_line(data: &BenchData) -> String {
+    let mut out = Vec::new();
+
+    match data {
+        BenchData::Log {
+            log,
+            cat,
+            ts,
+            tid,
+            args,
+        } => {
+            write!(out, "{{\"type\":\"log\",\"log\":").unwrap();
+            write_str(&mut out, log);
+            write!(out, ",\"cat\":").unwrap();
+            write_str(&mut out, cat);
+            write!(out, ",\"ts\":").unwrap();
+            write_float(&mut out, *ts as f64);
+            write!(out, ",\"tid\":{},\"args\":", tid).unwrap();
+            write_args(&mut out, args);
+        }
+        BenchData::Bench {
+            name,
+            cat,
+            ts,
+            dur,
+            tid,
+            args,
+            id,
+            parent,
+            depth,
+        } => {
+            write!(out, "{{\"type\":\"bench\",\"name\":").unwrap();
+            write_str(&mut out, name);
+            write!(out, ",\"cat\":").unwrap();
+            write_str(&mut out, cat);
+            write!(out, ",\"ts\":").unwrap();
+            write_float(&mut out, *ts as f64);
+            write!(out, ",\"dur\":").unwrap();
+            write_float(&mut out, *dur as f64);
+            write!(out, ",\"tid\":{},\"id\":{},\"parent\":", tid, id).unwrap();
+            match parent {
+                Some(parent) => write!(out, "{}", parent).unwrap(),
+                None => write!(out, "null").unwrap(),
+            }
+            write!(out, ",\"depth\":{},\"args\":", depth).unwrap();
+            write_args(&mut out, args);
+        }
+        BenchData::Count {
+            name,
+            cat,
+            data,
+            ts,
+            tid,
+        } => {
+            write!(out, "{{\"type\":\"count\",\"name\":").unwrap();
+            write_str(&mut out, name);
+            write!(out, ",\"cat\":").unwrap();
+            write_str(&mut out, cat);
+            write!(out, ",\"ts\":").unwrap();
+            write_float(&mut out, *ts as f64);
+            write!(out, ",\"tid\":{},\"data\":{{", tid).unwrap();
+            for (i, (name, value)) in data.iter().enumerate() {
+                if i != 0 {
+                    write!(out, ",").unwrap();
+                }
+                write_str(&mut out, name);
+                write!(out, ":").unwrap();
+                write_float(&mut out, *value as f64);
+            }
+            write!(out, "}}").unwrap();
+        }
+        BenchData::Meta { name, value } => {
+            write!(out, "{{\"type\":\"meta\",\"name\":").unwrap();
+            write_str(&mut out, name);
+            write!(out, ",\"value\":").unwrap();
+            write_arg(&mut out, value);
+        }
+    }
+
+    writeln!(out, "}}").unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// connection to a client
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    fn try_clone(&self) -> io::Result<Socket> {
+        match self {
+            Socket::Tcp(stream) => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.try_clone().map(Socket::Unix),
+        }
+    }
+
+    // makes the writes of the thread that serves the client fail
+    fn shutdown(&self) {
+        let _ = match self {
+            Socket::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// Client of the live stream
+struct Client {
+    lines: SyncSender<Arc<str>>,
+    // sequence number of the last event in the snapshot the client
+    // got, the later events are sent to it
+    seq: u64,
+}
+
+// Clients of the live stream
+struct Clients {
+    clients: Mutex<Vec<Client>>,
+    // threads that send the events to the clients with their sockets
+    threads: Mutex<Vec<(JoinHandle<()>, Socket)>>,
+    ended: AtomicBool,
+}
+
+// sends the events to the clients, formatting them outside of the
+// collector locks
+fn send_events(events: Receiver<Event>, clients: &Clients) {
+    for (seq, data) in events {
+        let mut clients = clients.clients.lock().unwrap();
+        if clients.iter().all(|client| seq <= client.seq) {
+            continue;
+        }
+
+        // the clients that can not keep up are disconnected
+        let line = Arc::<str>::from(json_line(&data));
+        clients.retain(|client| seq <= client.seq || client.lines.try_send(line.clone()).is_ok());
+    }
+}
+
+// lets the clients read the last events and stops serving them
+fn finish(clients: &Clients, accept: JoinHandle<()>, endpoint: &Endpoint) {
+    // the clients that are waiting to be accepted get the snapshot
+    clients.ended.store(true, Ordering::SeqCst);
+    let _ = accept.join();
+    clients.clients.lock().unwrap().clear();
+
+    // the clients that are not reading are disconnected
+    let threads = mem::take(&mut *clients.threads.lock().unwrap());
+    let deadline = Instant::now() + FINISH_TIMEOUT;
+    while Instant::now() < deadline && threads.iter().any(|(thread, _)| !thread.is_finished()) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    for (thread, socket) in threads {
+        if !thread.is_finished() {
+            socket.shutdown();
+        }
+        let _ = thread.join();
+    }
+
+    // the listener may not stop before the process exits
+    #[cfg(unix)]
+    {
+        if let Endpoint::Unix(path) = endpoint {
+            remove_socket(path);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = endpoint;
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(endpoint: &Endpoint) -> io::Result<(Listener, Endpoint)> {
+        match endpoint {
+            Endpoint::Tcp(port) => {
+                let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, *port))?;
+                listener.set_nonblocking(true)?;
+                let port = listener.local_addr()?.port();
+                Ok((Listener::Tcp(listener), Endpoint::Tcp(port)))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                // the socket is removed only if no process serves it
+                match UnixStream::connect(path) {
+                    Ok(_) => return Err(io::Error::from(ErrorKind::AddrInUse)),
+                    Err(e) if e.kind() == ErrorKind::ConnectionRefused => remove_socket(path),
+                    Err(_) => {}
+                }
+
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                let endpoint = Endpoint::Unix(path.clone());
+                Ok((Listener::Unix(listener), endpoint))
+            }
+        }
+    }
+
+    // accepts a client if there is one waiting
+    fn accept(&self) -> io::Result<Option<Socket>> {
+        let result = match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| tcp_client(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| unix_client(stream)),
+        };
+
+        match result {
+            Ok(client) => client.map(Some),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// removes the socket left by a previous run, other files are
+// kept so binding to them fails
+#[cfg(unix)]
+fn remove_socket(path: &std::path::Path) {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn tcp_client(stream: TcpStream) -> io::Result<Socket> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(Socket::Tcp(stream))
+}
+
+#[cfg(unix)]
+fn unix_client(stream: UnixStream) -> io::Result<Socket> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(Socket::Unix(stream))
+}
+
+// sends the snapshot and then the events to a client
+fn serve(client: Socket, snapshot: Vec<BenchData>, lines: Receiver<Arc<str>>) -> io::Result<()> {
+    let mut out = io::BufWriter::new(client);
+    for data in snapshot.iter() {
+        out.write_all(json_line(data).as_bytes())?;
+    }
+    out.flush()?;
+
+    while let Ok(line) = lines.recv() {
+        out.write_all(line.as_bytes())?;
+
+        // the lines that are already waiting are sent together
+        loop {
+            match lines.try_recv() {
+                Ok(line) => out.write_all(line.as_bytes())?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return out.flush(),
+            }
+        }
+        out.flush()?;
+    }
+
+    out.flush()
+}
+
+// starts serving the client
+fn add_client(client: Socket, store: &Store, clients: &Clients) -> io::Result<()> {
+    let socket = client.try_clone()?;
+    let (sender, receiver) = mpsc::sync_channel(BUFFER);
+
+    // the client gets every event exactly once, either in the
+    // snapshot or from the stream, as the events are not sent
+    // to the clients while the snapshot is taken
+    let mut list = clients.clients.lock().unwrap();
+    let (snapshot, seq) = store.sequenced_snapshot();
+    list.push(Client { lines: sender, seq });
+    drop(list);
+
+    let thread = thread::spawn(move || {
+        let _ = serve(client, snapshot, receiver);
+    });
+    let mut threads = clients.threads.lock().unwrap();
+    threads.retain(|(thread, _)| !thread.is_finished());
+    threads.push((thread, socket));
+    Ok(())
+}
+
+fn accept_clients(listener: Listener, store: Arc<Store>, clients: Arc<Clients>) {
+    loop {
+        // the clients that connected before the end are accepted
+        let ended = clients.ended.load(Ordering::SeqCst);
+
+        while let Ok(Some(client)) = listener.accept() {
+            let _ = add_client(client, &store, &clients);
+        }
+
+        if ended {
+            return;
+        }
+        thread::sleep(POLL);
+    }
+}
+
+// serves the events of `collector` to the clients of `endpoint`
+pub(crate) fn listen(collector: &Collector, endpoint: Endpoint) -> io::Result<Endpoint> {
+    let (listener, endpoint) = Listener::bind(&endpoint)?;
+
+    let clients = Arc::new(Clients {
+        clients: Mutex::new(Vec::new()),
+        threads: Mutex::new(Vec::new()),
+        ended: AtomicBool::new(false),
+    });
+
+    let store = collector.store();
+    let accepted = clients.clone();
+    let accept = thread::spawn(move || accept_clients(listener, store, accepted));
+
+    let served = endpoint.clone();
+    collector.add_stream(move |_| {
+        Stream::spawn(move |events| {
+            send_events(events, &clients);
+            finish(&clients, accept, &served);
+        })
+    });
+
+    Ok(endpoint)
+}
+
+// Value of the json of the live format
+enum Value<'a> {
+    Null,
+    Bool(bool),
+    // text of the number, so the ints are not rounded
+    Number(&'a str),
+    Str(String),
+    Object(Vec<(String, Value<'a>)>),
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// Parser of one line of json
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> io::Result<()> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(invalid("invalid json"))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.text[self.pos..].starts_with(keyword) {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> io::Result<Value<'a>> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'"') => self.string().map(Value::Str),
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let start = self.pos;
+                let len = self.text[start..]
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(self.text.len() - start);
+                self.pos += len;
+                Ok(Value::Number(&self.text[start..start + len]))
+            }
+            _ if self.keyword("null") => Ok(Value::Null),
+            _ if self.keyword("true") => Ok(Value::Bool(true)),
+            _ if self.keyword("false") => Ok(Value::Bool(false)),
+            _ => Err(invalid("invalid json")),
+        }
+    }
+
+    fn object(&mut self) -> io::Result<Value<'a>> {
+        self.expect(b'{')?;
+
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+
+        loop {
+            self.skip_space();
+            let name = self.string()?;
+            self.expect(b':')?;
+            fields.push((name, self.value()?));
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(invalid("invalid json")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.expect(b'"')?;
+
+        let mut s = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let mut code = 0;
+                        for _ in 0..4 {
+                            let digit = chars.next().and_then(|(_, c)| c.to_digit(16));
+                            code = code * 16 + digit.ok_or_else(|| invalid("invalid escape"))?;
+                        }
+                        s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    _ => return Err(invalid("invalid escape")),
+                },
+                c => s.push(c),
+            }
+        }
+
+        Err(invalid("unterminated string"))
+    }
+}
+
+fn field<'a, 'b>(fields: &'b [(String, Value<'a>)], name: &str) -> io::Result<&'b Value<'a>> {
+    fields
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| value)
+        .ok_or_else(|| invalid("missing field"))
+}
+
+fn string(fields: &[(String, Value)], name: &str) -> io::Result<String> {
+    match field(fields, name)? {
+        Value::Str(s) => Ok(s.clone()),
+        _ => Err(invalid("invalid field")),
+    }
+}
+
+fn number<T: std::str::FromStr>(fields: &[(String, Value)], name: &str) -> io::Result<T> {
+    match field(fields, name)? {
+        Value::Number(n) => n.parse().map_err(|_| invalid("invalid number")),
+        _ => Err(invalid("invalid field")),
+    }
+}
+
+fn float(value: &Value) -> io::Result<f64> {
+    match value {
+        Value::Number(n) => n.parse().map_err(|_| invalid("invalid number")),
+        Value::Null => Ok(f64::NAN),
+        _ => Err(invalid("invalid number")),
+    }
+}
+
+fn arg(value: &Value) -> io::Result<Arg> {
+    match value {
+        Value::Number(n) if n.contains(['.', 'e', 'E']) => float(value).map(Arg::Float),
+        Value::Number(n) => n
+            .parse()
+            .map(Arg::Int)
+            .map_err(|_| invalid("invalid number")),
+        Value::Null => Ok(Arg::Float(f64::NAN)),
+        Value::Str(s) => Ok(Arg::Str(s.clone())),
+        Value::Bool(b) => Ok(Arg::Bool(*b)),
+        Value::Object(_) => Err(invalid("invalid arg")),
+    }
+}
+
+fn object<'a, 'b>(
+    fields: &'b [(String, Value<'a>)],
+    name: &str,
+) -> io::Result<&'b [(String, Value<'a>)]> {
+    match field(fields, name)? {
+        Value::Object(fields) => Ok(fields),
+        _ => Err(invalid("invalid field")),
+    }
+}
+
+fn args(fields: &[(String, Value)]) -> io::Result<Vec<(String, Arg)>> {
+    object(fields, "args")?
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), arg(value)?)))
+        .collect()
+}
+
+fn parse_line(line: &str) -> io::Result<BenchData> {
+    let mut parser = Parser { text: line, pos: 0 };
+    let fields = match parser.value()? {
+        Value::Object(fields) => fields,
+        _ => return Err(invalid("event is not an object")),
+    };
+    if parser.peek().is_some() {
+        return Err(invalid("invalid json"));
+    }
+
+    let ts = |fields: &[(String, Value)]| float(field(fields, "ts")?).map(|ts| ts as f32);
+
+    match string(&fields, "type")?.as_str() {
+        "log" => Ok(BenchData::Log {
+            log: string(&fields, "log")?,
+            cat: string(&fields, "cat")?,
+            ts: ts(&fields)?,
+            tid: number(&fields, "tid")?,
+            args: args(&fields)?,
+        }),
+        "bench" => Ok(BenchData::Bench {
+            name: string(&fields, "name")?.into(),
+            cat: string(&fields, "cat")?.into(),
+            ts: ts(&fields)?,
+            dur: float(field(&fields, "dur")?)? as f32,
+            tid: number(&fields, "tid")?,
+            args: args(&fields)?,
+            id: number(&fields, "id")?,
+            parent: match field(&fields, "parent")? {
+                Value::Null => None,
+                _ => Some(number(&fields, "parent")?),
+            },
+            depth: number(&fields, "depth")?,
+        }),
+        "count" => Ok(BenchData::Count {
+            name: string(&fields, "name")?,
+            cat: string(&fields, "cat")?,
+            data: object(&fields, "data")?
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), float(value)? as f32)))
+                .collect::<io::Result<_>>()?,
+            ts: ts(&fields)?,
+            tid: number(&fields, "tid")?,
+        }),
+        "meta" => Ok(BenchData::Meta {
+            name: string(&fields, "name")?,
+            value: arg(field(&fields, "value")?)?,
+        }),
+        _ => Err(invalid("unknown event type")),
+    }
+}
+
+/// Reader of the live stream
+///
+/// The reader yields the events sent by [Instantiator::listen],
+/// starting with the events collected before the client connected.
+/// The stream ends when the collection ends.
+///
+/// [Instantiator::listen]: struct.Instantiator.html#method.listen
+pub struct LiveReader<R: Read> {
+    input: BufReader<R>,
+    line: String,
+}
+
+impl<R: Read> LiveReader<R> {
+    /// Constructs the reader of `input`
+    pub fn new(input: R) -> LiveReader<R> {
+        LiveReader {
+            input: BufReader::new(input),
+            line: String::new(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for LiveReader<R> {
+    type Item = io::Result<BenchData>;
+
+    fn next(&mut self) -> Option<io::Result<BenchData>> {
+        loop {
+            self.line.clear();
+            match self.input.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) if self.line.trim().is_empty() => continue,
+                Ok(_) => return Some(parse_line(&self.line)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn round_trip(data: &BenchData) -> BenchData {
+        let line = json_line(data);
+        assert!(line.ends_with('\n'));
+        parse_line(&line).unwrap()
+    }
+
+    fn assert_round_trip(data: BenchData) {
+        assert_eq!(format!("{:?}", round_trip(&data)), format!("{:?}", data));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_round_trip(BenchData::Log {
+            log: String::from("quote \" backslash \\ slash / \n\r\t \u{1} \u{7f} ü 😀"),
+            cat: String::from("a\"b"),
+            ts: 1.5,
+            tid: 3,
+            args: vec![(String::from("new\nline"), Arg::Str(String::from("\\\"")))],
+        });
+    }
+
+    #[test]
+    fn int_and_float_args() {
+        assert_round_trip(BenchData::Log {
+            log: String::from("Args"),
+            cat: String::from("log"),
+            ts: 0.0,
+            tid: 0,
+            args: vec![
+                (String::from("int"), Arg::Int(2)),
+                (String::from("float"), Arg::Float(2.0)),
+                (String::from("big"), Arg::Float(1e20)),
+                (String::from("small"), Arg::Float(-2.5e-8)),
+                (String::from("min"), Arg::Int(i64::MIN)),
+                (String::from("max"), Arg::Int(i64::MAX)),
+                (String::from("bool"), Arg::Bool(false)),
+            ],
+        });
+    }
+
+    #[test]
+    fn not_finite_is_null() {
+        let data = BenchData::Count {
+            name: String::from("Counter"),
+            cat: String::from("count"),
+            ts: f32::NAN,
+            tid: 1,
+            data: vec![
+                (String::from("nan"), f32::NAN),
+                (String::from("inf"), f32::INFINITY),
+            ],
+        };
+        assert!(json_line(&data).contains("\"ts\":null"));
+
+        match round_trip(&data) {
+            BenchData::Count { ts, data, .. } => {
+                assert!(ts.is_nan());
+                assert!(data.iter().all(|(_, value)| value.is_nan()));
+            }
+            data => panic!("unexpected {:?}", data),
+        }
+
+        let meta = BenchData::Meta {
+            name: String::from("value"),
+            value: Arg::Float(f64::NAN),
+        };
+        match round_trip(&meta) {
+            BenchData::Meta {
+                value: Arg::Float(value),
+                ..
+            } => assert!(value.is_nan()),
+            data => panic!("unexpected {:?}", data),
+        }
+    }
+
+    #[test]
+    fn scopes() {
+        let scope = |parent| BenchData::Bench {
+            name: Cow::Borrowed("Scope"),
+            cat: Cow::Borrowed("function"),
+            ts: -12.25,
+            dur: 3.5,
+            tid: 2,
+            args: vec![(String::from("n"), Arg::Int(-1))],
+            id: u64::MAX,
+            parent,
+            depth: 4,
+        };
+
+        assert!(json_line(&scope(None)).contains("\"parent\":null"));
+        assert_round_trip(scope(None));
+        assert_round_trip(scope(Some(7)));
+    }
+
+    #[test]
+    fn meta() {
+        let values = [
+            Arg::Int(-5),
+            Arg::Float(0.125),
+            Arg::Str(String::from("text")),
+            Arg::Bool(true),
+        ];
+        for value in values {
+            assert_round_trip(BenchData::Meta {
+                name: String::from("meta"),
+                value,
+            });
+        }
+    }
+
+    #[test]
+    fn invalid_lines() {
+        for line in &[
+            "",
+            "[]",
+            "{\"type\":\"log\"}",
+            "{\"type\":\"unknown\"}",
+            "{\"type\":\"meta\",\"name\":\"a\",\"value\":1} x",
+            "{\"type\":\"meta\",\"name\":\"a\\q\",\"value\":1}",
+            "{\"type\":\"meta\",\"name\":\"a",
+        ] {
+            assert!(parse_line(line).is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn client_connected_before_end() {
+        let collector = Collector::new(Vec::new());
+        let port = match collector.listen(Endpoint::Tcp(0)).unwrap() {
+            Endpoint::Tcp(port) => port,
+            _ => unreachable!(),
+        };
+        for i in 0..10 {
+            let _scope = collector.scope(format!("Scope {}", i));
+        }
+
+        // the collection ends before the client is accepted
+        let client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        collector.end();
+
+        let scopes = LiveReader::new(client)
+            .map(Result::unwrap)
+            .filter(|data| matches!(data, BenchData::Bench { .. }))
+            .count();
+        assert_eq!(scopes, 10);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_in_use() {
+        let path = std::env::temp_dir().join(format!("gbench-test-{}.sock", std::process::id()));
+        let collector = Collector::new(Vec::new());
+        collector.listen(Endpoint::Unix(path.clone())).unwrap();
+
+        let error = Collector::new(Vec::new())
+            .listen(Endpoint::Unix(path.clone()))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AddrInUse);
+
+        // the socket of the first collector is still served
+        let client = UnixStream::connect(&path).unwrap();
+        collector.end();
+        let meta = LiveReader::new(client).next().unwrap().unwrap();
+        assert!(matches!(meta, BenchData::Meta { .. }));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,112 @@
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::bench::log_with_args;
+use crate::filter;
+use crate::global::{instantiated, Arg};
+
+/// Logger that saves the records of the log crate to the trace
+///
+/// Every record with a level that passes the level filter becomes a
+/// [BenchData::Log] of category "log" with "level", "target", "module"
+/// and "line" args. The records are matched by the [Filter] with their
+/// target as the name. The records can also be forwarded to another
+/// logger, so they still get printed.
+///
+/// This struct is available with `log` feature.
+///
+/// [BenchData::Log]: enum.BenchData.html#variant.Log
+/// [Filter]: struct.Filter.html
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, ChromeTracing, LogBridge};
+/// use log::LevelFilter;
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///     LogBridge::new(LevelFilter::Info).init().unwrap();
+///
+///     log::info!("Saved to the trace");
+///     log::debug!("Not saved to the trace");
+/// }
+/// ```
+pub struct LogBridge {
+    level: LevelFilter,
+    forward: Option<(Box<dyn Log>, LevelFilter)>,
+}
+
+impl LogBridge {
+    /// Constructs the logger that saves the records up to `level`
+    pub fn new(level: LevelFilter) -> LogBridge {
+        LogBridge {
+            level,
+            forward: None,
+        }
+    }
+
+    /// Forwards the records up to `level` to `logger`
+    pub fn forward_to(mut self, logger: Box<dyn Log>, level: LevelFilter) -> LogBridge {
+        self.forward = Some((logger, level));
+        self
+    }
+
+    /// Sets this logger as the logger of the log crate
+    ///
+    /// The max level of the log crate is set to the highest of the
+    /// levels of this logger and the forwarded logger.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let max_level = match &self.forward {
+            Some((_, level)) => self.level.max(*level),
+            None => self.level,
+        };
+
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Log for LogBridge {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+            || match &self.forward {
+                Some((logger, level)) => metadata.level() <= *level && logger.enabled(metadata),
+                None => false,
+            }
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.level && instantiated() && filter::enabled(record.target(), "log")
+        {
+            let mut args = vec![
+                (String::from("level"), Arg::Str(record.level().to_string())),
+                (
+                    String::from("target"),
+                    Arg::Str(String::from(record.target())),
+                ),
+            ];
+
+            if let Some(module) = record.module_path() {
+                args.push((String::from("module"), Arg::Str(String::from(module))));
+            }
+            if let Some(line) = record.line() {
+                args.push((String::from("line"), Arg::Int(line as i64)));
+            }
+
+            log_with_args(record.args().to_string(), String::from("log"), args);
+        }
+
+        if let Some((logger, level)) = &self.forward {
+            if record.level() <= *level && logger.enabled(record.metadata()) {
+                logger.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some((logger, _)) = &self.forward {
+            logger.flush();
+        }
+    }
+}
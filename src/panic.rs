@@ -0,0 +1,139 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::panic;
+use std::sync::Once;
+use std::thread;
+
+use crate::global::{current, Arg};
+
+// completed when the hook is installed
+static INSTALLED: Once = Once::new();
+
+thread_local! {
+    // whether the panic hook is running on the thread
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+// whether the locks have to be taken without waiting, because the
+// thread could have panicked while holding one of them
+pub(crate) fn in_hook() -> bool {
+    IN_HOOK.try_with(Cell::get).unwrap_or(false)
+}
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        String::from(*s)
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+/// Installs a panic hook that saves the panic to the trace
+///
+/// When a thread panics the hook logs an instant event named
+/// "panic: *message*" of category "panic" with "location" and
+/// "thread" args. After that
+/// the previously installed hook is called.
+///
+/// The scopes of the panicking thread are ended while the thread
+/// unwinds and are marked with "unwound" arg. If the panic strategy
+/// is abort the hook also ends the scopes of all the threads,
+/// marking them as "unwound", finishes the stream writers and gives
+/// the collected data to the writers, because the program is aborted
+/// right after the hook.
+///
+/// The hook is installed once, calling this function again does
+/// nothing.
+///
+/// The hook does not wait for the locks of the collector, so if the
+/// panic happens while the collector is used, for example in a
+/// writer, the panic may be missing from the trace.
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{install_panic_hook, instantiate, scope, ChromeTracing};
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///     install_panic_hook();
+///
+///     let worker = std::thread::spawn(|| {
+///         scope!(sc | "Worker");
+///         panic!("worker failed");
+///     });
+///
+///     assert!(worker.join().is_err());
+/// }
+/// ```
+///
+/// Installing the hook again does not record the panics twice
+///
+/// ```rust
+/// use gbench::{install_panic_hook, instantiate, BenchData, Writer};
+///
+/// // Checks that the panic is logged once
+/// struct PanicCheck;
+///
+/// impl Writer for PanicCheck {
+///     fn end(&self, data: &[BenchData]) {
+///         let panics = data
+///             .iter()
+///             .filter(|data| matches!(data, BenchData::Log { cat, .. } if cat == "panic"))
+///             .count();
+///         assert_eq!(panics, 1);
+///     }
+/// }
+///
+/// fn main() {
+///     instantiate!(PanicCheck);
+///     install_panic_hook();
+///     install_panic_hook();
+///
+///     assert!(std::thread::spawn(|| panic!("failed")).join().is_err());
+/// }
+/// ```
+pub fn install_panic_hook() {
+    INSTALLED.call_once(install);
+}
+
+fn install() {
+    let previous = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        // a panic in the hook is not recorded again
+        let nested = IN_HOOK.try_with(|hook| hook.replace(true)).unwrap_or(true);
+        if nested {
+            previous(info);
+            return;
+        }
+
+        if let Some(collector) = current() {
+            let mut args = Vec::new();
+
+            if let Some(location) = info.location() {
+                args.push((String::from("location"), Arg::Str(location.to_string())));
+            }
+            if let Some(name) = thread::current().name() {
+                args.push((String::from("thread"), Arg::Str(String::from(name))));
+            }
+
+            collector.log(
+                format!("panic: {}", message(info.payload())),
+                String::from("panic"),
+                args,
+            );
+
+            if cfg!(panic = "abort") {
+                collector.end_open_scopes("unwound");
+                collector.finish_streams();
+                collector.dump();
+            }
+        }
+
+        IN_HOOK.with(|hook| hook.set(false));
+        previous(info);
+    }));
+}
@@ -0,0 +1,114 @@
+#[cfg(target_os = "linux")]
+use std::fs;
+
+#[cfg(target_os = "linux")]
+use crate::bench::count;
+
+// Process resource counters read from /proc
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn sysconf(name: std::os::raw::c_int) -> std::os::raw::c_long;
+}
+
+#[cfg(target_os = "linux")]
+const SC_CLK_TCK: std::os::raw::c_int = 2;
+
+// clock ticks per second used in /proc/*/stat
+#[cfg(target_os = "linux")]
+fn clock_ticks() -> f32 {
+    match unsafe { sysconf(SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f32,
+        _ => 100.0,
+    }
+}
+
+// value of a "Name:   value kB" line in /proc/self/status in bytes
+#[cfg(target_os = "linux")]
+fn status_bytes(status: &str, key: &str) -> Option<f32> {
+    let line = status.lines().find(|line| line.starts_with(key))?;
+    let kb = line[key.len()..].trim().trim_end_matches("kB").trim();
+    kb.parse::<f32>().ok().map(|kb| kb * 1024.0)
+}
+
+// (command name, cpu time in ms) from a stat file
+#[cfg(target_os = "linux")]
+fn read_stat(path: &str, ticks: f32) -> Option<(String, f32)> {
+    let stat = fs::read_to_string(path).ok()?;
+
+    // the command name can contain spaces and parentheses
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let comm = stat[open + 1..close].to_string();
+
+    // fields after the command name start with field 3 (state)
+    let fields = stat[close + 1..].split_whitespace().collect::<Vec<_>>();
+    let utime = fields.get(11)?.parse::<f32>().ok()?;
+    let stime = fields.get(12)?.parse::<f32>().ok()?;
+
+    Some((comm, (utime + stime) / ticks * 1000.0))
+}
+
+#[cfg(target_os = "linux")]
+pub fn sample() {
+    let ticks = clock_ticks();
+
+    if let Ok(status) = fs::read_to_string("/proc/self/status") {
+        let mut memory = Vec::new();
+
+        if let Some(rss) = status_bytes(&status, "VmRSS:") {
+            memory.push((String::from("rss"), rss));
+        }
+        if let Some(virt) = status_bytes(&status, "VmSize:") {
+            memory.push((String::from("virtual"), virt));
+        }
+
+        if !memory.is_empty() {
+            count(String::from("Memory"), String::from("count"), memory);
+        }
+    }
+
+    let mut cpu = Vec::new();
+
+    if let Some((_, total)) = read_stat("/proc/self/stat", ticks) {
+        cpu.push((String::from("total"), total));
+    }
+
+    let mut threads = 0;
+
+    if let Ok(tasks) = fs::read_dir("/proc/self/task") {
+        for task in tasks.flatten() {
+            threads += 1;
+
+            let tid = task.file_name().to_string_lossy().into_owned();
+            if let Some((comm, time)) = read_stat(&format!("/proc/self/task/{}/stat", tid), ticks) {
+                cpu.push((format!("{} ({})", comm, tid), time));
+            }
+        }
+    }
+
+    if !cpu.is_empty() {
+        count(String::from("CPU time"), String::from("count"), cpu);
+    }
+
+    if threads > 0 {
+        count(
+            String::from("Threads"),
+            String::from("count"),
+            vec![(String::from("count"), threads as f32)],
+        );
+    }
+
+    if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+        // the directory being read is open as one of the descriptors
+        let open = fds.count().saturating_sub(1);
+        count(
+            String::from("File descriptors"),
+            String::from("count"),
+            vec![(String::from("count"), open as f32)],
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sample() {}
@@ -0,0 +1,234 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::global::BenchData;
+
+/// Part of the collected data that is kept in flight recorder mode
+///
+/// See [Instantiator::flight_recorder].
+///
+/// [Instantiator::flight_recorder]: struct.Instantiator.html#method.flight_recorder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// Keep the last N events
+    Events(usize),
+    /// Keep the events that ended during the last T before
+    /// the current moment
+    Time(Duration),
+}
+
+// timestamp of the moment the event was recorded
+fn recorded_ts(data: &BenchData) -> f32 {
+    match data {
+        BenchData::Log { ts, .. } => *ts,
+        BenchData::Bench { ts, dur, .. } => ts + dur,
+        BenchData::Count { ts, .. } => *ts,
+        BenchData::Meta { .. } => 0.0,
+    }
+}
+
+// Storage of the collected data, `now` is the timestamp of
+// the current moment that the time window ends at
+pub struct Queue {
+    // metadata is never removed by the window
+    meta: Vec<BenchData>,
+    events: VecDeque<BenchData>,
+    window: Option<Window>,
+    // number of the pushed events, which is the
+    // sequence number of the last pushed event
+    pushed: u64,
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue {
+            meta: Vec::new(),
+            events: VecDeque::new(),
+            window: None,
+            pushed: 0,
+        }
+    }
+
+    pub fn set_window(&mut self, window: Window, now: f32) {
+        self.window = Some(window);
+        self.trim(now);
+    }
+
+    // returns the sequence number of the event
+    pub fn push(&mut self, data: BenchData, now: f32) -> u64 {
+        if let BenchData::Meta { .. } = data {
+            self.meta.push(data);
+        } else {
+            self.events.push_back(data);
+            self.trim(now);
+        }
+
+        self.pushed += 1;
+        self.pushed
+    }
+
+    pub fn pushed(&self) -> u64 {
+        self.pushed
+    }
+
+    pub fn meta(&self) -> &[BenchData] {
+        &self.meta
+    }
+
+    // copy of the collected data, metadata first
+    pub fn snapshot(&mut self, now: f32) -> Vec<BenchData> {
+        self.trim_all(now);
+        self.meta
+            .iter()
+            .chain(self.events.iter())
+            .cloned()
+            .collect()
+    }
+
+    // takes the collected data out of the queue, metadata first
+    pub fn take(&mut self, now: f32) -> Vec<BenchData> {
+        self.trim_all(now);
+        self.meta.drain(..).chain(self.events.drain(..)).collect()
+    }
+
+    // takes the collected events out of the queue with a copy
+    // of the metadata, metadata first
+    pub fn drain(&mut self, now: f32) -> Vec<BenchData> {
+        self.trim_all(now);
+        self.meta
+            .iter()
+            .cloned()
+            .chain(self.events.drain(..))
+            .collect()
+    }
+
+    // removes the collected events, keeping the metadata
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    // start of the time window
+    fn window_start(&self, now: f32) -> Option<f32> {
+        match self.window {
+            Some(Window::Time(time)) => Some(now - time.as_secs_f32() * 1_000_000.0),
+            _ => None,
+        }
+    }
+
+    // removes the events that are out of the window
+    fn trim(&mut self, now: f32) {
+        if let Some(Window::Events(count)) = self.window {
+            while self.events.len() > count {
+                self.events.pop_front();
+            }
+        }
+
+        // the events of different threads can be queued slightly
+        // out of order, so the trimming stops at the first event
+        // in the window
+        if let Some(first) = self.window_start(now) {
+            while let Some(data) = self.events.front() {
+                if recorded_ts(data) >= first {
+                    break;
+                }
+                self.events.pop_front();
+            }
+        }
+    }
+
+    // removes the events that are out of the window, also the ones
+    // that were queued after the events in the window
+    fn trim_all(&mut self, now: f32) {
+        self.trim(now);
+        if let Some(first) = self.window_start(now) {
+            self.events.retain(|data| recorded_ts(data) >= first);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(ts: f32) -> BenchData {
+        BenchData::Log {
+            log: format!("Log {}", ts),
+            cat: String::from("log"),
+            ts,
+            tid: 0,
+            args: Vec::new(),
+        }
+    }
+
+    fn times(data: &[BenchData]) -> Vec<f32> {
+        data.iter().map(recorded_ts).collect()
+    }
+
+    // queue that keeps the events of the last 10 microseconds
+    fn time_window() -> Queue {
+        let mut queue = Queue::new();
+        queue.set_window(Window::Time(Duration::from_micros(10)), 0.0);
+        queue
+    }
+
+    #[test]
+    fn empty() {
+        let mut queue = time_window();
+        assert!(queue.snapshot(100.0).is_empty());
+        assert!(queue.take(100.0).is_empty());
+    }
+
+    #[test]
+    fn cut_off() {
+        let mut queue = time_window();
+        for ts in 0..=20 {
+            queue.push(log(ts as f32), ts as f32);
+        }
+
+        // the events recorded at the start of the window are kept
+        assert_eq!(
+            times(&queue.snapshot(20.0)),
+            (10..=20).map(|ts| ts as f32).collect::<Vec<_>>()
+        );
+        assert_eq!(times(&queue.snapshot(29.5)), [20.0]);
+        assert!(queue.snapshot(30.5).is_empty());
+    }
+
+    #[test]
+    fn window_is_trimmed_on_push() {
+        let mut queue = time_window();
+        for ts in 0..1000 {
+            queue.push(log(ts as f32), ts as f32);
+        }
+        assert_eq!(queue.events.len(), 11);
+    }
+
+    #[test]
+    fn meta_is_kept() {
+        let mut queue = time_window();
+        queue.push(
+            BenchData::Meta {
+                name: String::from("scope overhead"),
+                value: crate::global::Arg::Float(0.05),
+            },
+            0.0,
+        );
+        queue.push(log(0.0), 0.0);
+        assert_eq!(queue.snapshot(100.0).len(), 1);
+    }
+
+    #[test]
+    fn older_events_after_newer() {
+        let mut queue = time_window();
+        queue.push(log(15.0), 15.0);
+        queue.push(log(5.0), 15.0);
+        queue.push(log(16.0), 16.0);
+        queue.push(log(12.0), 16.0);
+
+        // 5 is behind an event in the window
+        assert_eq!(queue.events.len(), 4);
+        assert_eq!(times(&queue.snapshot(20.0)), [15.0, 16.0, 12.0]);
+        assert_eq!(times(&queue.take(24.0)), [15.0, 16.0]);
+        assert!(queue.take(24.0).is_empty());
+    }
+}
@@ -0,0 +1,420 @@
+// Helpers shared by the writers of human readable reports
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::global::{Arg, BenchData};
+
+// Statistics of the scopes with the same name
+pub struct ScopeStats<'a> {
+    pub name: &'a str,
+    pub count: usize,
+    // in microseconds
+    pub total: f32,
+    pub self_time: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+// durations of the scopes minus the durations of their children
+pub fn self_times(data: &[BenchData]) -> HashMap<u64, f32> {
+    let mut children = HashMap::new();
+    for data in data {
+        if let BenchData::Bench {
+            dur,
+            parent: Some(parent),
+            ..
+        } = data
+        {
+            *children.entry(*parent).or_insert(0.0) += dur;
+        }
+    }
+
+    data.iter()
+        .filter_map(|data| match data {
+            BenchData::Bench { id, dur, .. } => {
+                let children = children.get(id).copied().unwrap_or(0.0);
+                Some((*id, (dur - children).max(0.0)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// "scope overhead" saved by the collector, which is the time
+// in microseconds that one scope adds to its parent
+pub fn overhead(data: &[BenchData]) -> f32 {
+    data.iter()
+        .find_map(|data| match data {
+            BenchData::Meta {
+                name,
+                value: Arg::Float(overhead),
+            } if name == "scope overhead" => Some(*overhead as f32),
+            _ => None,
+        })
+        .unwrap_or(0.0)
+}
+
+// numbers of the scopes nested directly and at any depth in every scope
+fn nested_counts(data: &[BenchData]) -> HashMap<u64, (usize, usize)> {
+    // children are counted before their parents
+    let mut scopes = data
+        .iter()
+        .filter_map(|data| match data {
+            BenchData::Bench {
+                id, parent, depth, ..
+            } => Some((*depth, *id, *parent)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    scopes.sort_by_key(|scope| std::cmp::Reverse(scope.0));
+
+    let mut counts: HashMap<u64, (usize, usize)> = HashMap::new();
+    for (_, id, parent) in scopes {
+        let nested = counts.get(&id).map_or(0, |counts| counts.1);
+        if let Some(parent) = parent {
+            let counts = counts.entry(parent).or_default();
+            counts.0 += 1;
+            counts.1 += nested + 1;
+        }
+    }
+    counts
+}
+
+// statistics of the scopes grouped by name, longest total first. If
+// `weighted` the sampled scopes are counted as in `scope_tree`. If
+// `corrected` the "scope overhead" of the nested scopes is subtracted
+// from the total and self times
+pub fn scope_stats(data: &[BenchData], weighted: bool, corrected: bool) -> Vec<ScopeStats<'_>> {
+    let self_times = self_times(data);
+    let weights = if weighted {
+        weights(data)
+    } else {
+        HashMap::new()
+    };
+    let (overhead, nested) = if corrected {
+        (overhead(data), nested_counts(data))
+    } else {
+        (0.0, HashMap::new())
+    };
+
+    let mut stats: Vec<ScopeStats> = Vec::new();
+    let mut indexes = HashMap::new();
+    for data in data {
+        if let BenchData::Bench { name, dur, id, .. } = data {
+            let index = *indexes.entry(name.as_ref()).or_insert_with(|| {
+                stats.push(ScopeStats {
+                    name,
+                    count: 0,
+                    total: 0.0,
+                    self_time: 0.0,
+                    min: f32::INFINITY,
+                    max: 0.0,
+                });
+                stats.len() - 1
+            });
+
+            let (children, descendants) = nested.get(id).copied().unwrap_or((0, 0));
+            let dur = (dur - overhead * descendants as f32).max(0.0);
+            let self_time = (self_times[id] - overhead * children as f32).max(0.0);
+
+            let weight = weights.get(id).copied().unwrap_or(1);
+            let scale = weight as f32;
+            let stats = &mut stats[index];
+            stats.count += weight as usize;
+            stats.total += dur * scale;
+            stats.self_time += self_time * scale;
+            stats.min = stats.min.min(dur);
+            stats.max = stats.max.max(dur);
+        }
+    }
+
+    stats.sort_by(|a, b| b.total.total_cmp(&a.total));
+    stats
+}
+
+// first start and last end of the events
+pub fn time_range(data: &[BenchData]) -> (f32, f32) {
+    let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+    for data in data {
+        let (start, end) = match data {
+            BenchData::Log { ts, .. } => (*ts, *ts),
+            BenchData::Bench { ts, dur, .. } => (*ts, ts + dur),
+            BenchData::Count { ts, .. } => (*ts, *ts),
+            BenchData::Meta { .. } => continue,
+        };
+        range = (range.0.min(start), range.1.max(end));
+    }
+
+    if range.0 > range.1 {
+        (0.0, 1.0)
+    } else if range.0 == range.1 {
+        (range.0, range.0 + 1.0)
+    } else {
+        range
+    }
+}
+
+// name shortened to fit in `width` svg units
+pub fn fit(name: &str, width: f32) -> Option<String> {
+    let chars = ((width - 4.0) / 6.5) as usize;
+    let len = name.chars().count();
+    if len <= chars {
+        Some(name.to_string())
+    } else if chars >= 3 {
+        let mut name = name.chars().take(chars - 1).collect::<String>();
+        name.push('…');
+        Some(name)
+    } else {
+        None
+    }
+}
+
+// duration in microseconds with a readable unit
+pub fn format_time(us: f32) -> String {
+    if us >= 1_000_000.0 {
+        format!("{:.2} s", us / 1_000_000.0)
+    } else if us >= 1_000.0 {
+        format!("{:.2} ms", us / 1_000.0)
+    } else {
+        format!("{:.2} µs", us)
+    }
+}
+
+pub fn arg_text(arg: &Arg) -> String {
+    match arg {
+        Arg::Int(v) => v.to_string(),
+        Arg::Float(v) => v.to_string(),
+        Arg::Str(v) => v.clone(),
+        Arg::Bool(v) => v.to_string(),
+    }
+}
+
+// text of the args as "name: value, name: value"
+pub fn args_text(args: &[(String, Arg)]) -> String {
+    args.iter()
+        .map(|(name, value)| format!("{}: {}", name, arg_text(value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// escapes the text for html and svg
+pub fn escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+// hue that is the same for the same name in every report
+pub fn hue(name: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish() % 360
+}
+
+// number of executions that a sampled scope stands for
+fn weight(args: &[(String, Arg)]) -> u64 {
+    args.iter()
+        .find_map(|(name, value)| match value {
+            Arg::Int(weight) if name == "weight" && *weight > 0 => Some(*weight as u64),
+            _ => None,
+        })
+        .unwrap_or(1)
+}
+
+// number of executions that every scope stands for, which is its
+// own "weight" arg. The weighted reports count every scope this many
+// times, so their counts and times are estimates of all the executions
+// instead of only the collected ones. The scopes nested in a sampled
+// scope are collected also when the sampled scope is not, so they are
+// not scaled by the weights of their parents
+fn weights(data: &[BenchData]) -> HashMap<u64, u64> {
+    data.iter()
+        .filter_map(|data| match data {
+            BenchData::Bench { id, args, .. } => Some((*id, weight(args))),
+            _ => None,
+        })
+        .collect()
+}
+
+// Scopes with the same stack of names merged into one node
+pub struct Node<'a> {
+    pub name: &'a str,
+    pub count: usize,
+    // in microseconds
+    pub total: f32,
+    pub self_time: f32,
+    // number of scopes the node is nested in
+    pub depth: usize,
+    // longest total first, the children come after
+    // their parents in the tree
+    pub children: Vec<usize>,
+}
+
+// merges the scopes of all threads into a tree, the first node
+// is the root that has the outermost scopes as children. If
+// `weighted` the sampled scopes are counted as many times as
+// their "weight" args
+pub fn scope_tree(data: &[BenchData], weighted: bool) -> Vec<Node<'_>> {
+    let self_times = self_times(data);
+    let weights = if weighted {
+        weights(data)
+    } else {
+        HashMap::new()
+    };
+
+    // parents are merged before their children
+    let mut scopes = data
+        .iter()
+        .filter_map(|data| match data {
+            BenchData::Bench {
+                name,
+                dur,
+                id,
+                parent,
+                depth,
+                ..
+            } => Some((*depth, name.as_ref(), *dur, *id, *parent)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    scopes.sort_by_key(|scope| scope.0);
+
+    let mut tree = vec![Node {
+        name: "",
+        count: 0,
+        total: 0.0,
+        self_time: 0.0,
+        depth: 0,
+        children: Vec::new(),
+    }];
+    let mut nodes = HashMap::new();
+    let mut spans = HashMap::new();
+
+    for (_, name, dur, id, parent) in scopes {
+        // scopes without a collected parent are the outermost
+        let parent = parent.and_then(|parent| spans.get(&parent)).copied();
+        let parent = parent.unwrap_or(0);
+        let weight = weights.get(&id).copied().unwrap_or(1);
+
+        let node = *nodes.entry((parent, name)).or_insert_with(|| {
+            let depth = tree[parent].depth + 1;
+            tree.push(Node {
+                name,
+                count: 0,
+                total: 0.0,
+                self_time: 0.0,
+                depth,
+                children: Vec::new(),
+            });
+            let node = tree.len() - 1;
+            tree[parent].children.push(node);
+            node
+        });
+
+        let scale = weight as f32;
+        tree[node].count += weight as usize;
+        tree[node].total += dur * scale;
+        tree[node].self_time += self_times[&id] * scale;
+        if parent == 0 {
+            tree[0].total += dur * scale;
+        }
+        spans.insert(id, node);
+    }
+
+    for i in 0..tree.len() {
+        let mut children = std::mem::take(&mut tree[i].children);
+        children.sort_by(|&a, &b| tree[b].total.total_cmp(&tree[a].total));
+        tree[i].children = children;
+    }
+
+    tree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(name: &'static str, id: u64, parent: Option<u64>, depth: usize) -> BenchData {
+        let args = if name == "Sampled" {
+            vec![(String::from("weight"), Arg::Int(10))]
+        } else {
+            Vec::new()
+        };
+
+        BenchData::Bench {
+            name: Cow::Borrowed(name),
+            cat: Cow::Borrowed(""),
+            ts: id as f32 * 10.0,
+            dur: 1.0,
+            tid: 0,
+            args,
+            id,
+            parent,
+            depth,
+        }
+    }
+
+    // "Outer" runs "Sampled" 100 times, which is collected in 1 of 10
+    // executions and runs "Child" that is collected every time
+    fn sampled_parent() -> Vec<BenchData> {
+        let mut data = Vec::new();
+        let mut id = 1;
+        for i in 0..100 {
+            if i % 10 == 0 {
+                data.push(scope("Sampled", id, Some(0), 2));
+                data.push(scope("Child", id + 1, Some(id), 3));
+                id += 2;
+            } else {
+                data.push(scope("Child", id, Some(0), 2));
+                id += 1;
+            }
+        }
+        data.push(scope("Outer", 0, None, 1));
+        data
+    }
+
+    #[test]
+    fn children_of_sampled_out_parents() {
+        let data = sampled_parent();
+
+        let stats = scope_stats(&data, true, false);
+        let count = |name| stats.iter().find(|s| s.name == name).unwrap().count;
+        assert_eq!(count("Sampled"), 100);
+        assert_eq!(count("Child"), 100);
+        assert_eq!(count("Outer"), 1);
+
+        let tree = scope_tree(&data, true);
+        let children = tree
+            .iter()
+            .filter(|node| node.name == "Child")
+            .map(|node| node.count)
+            .sum::<usize>();
+        assert_eq!(children, 100);
+    }
+
+    #[test]
+    fn unweighted() {
+        let data = sampled_parent();
+
+        let stats = scope_stats(&data, false, false);
+        let count = |name| stats.iter().find(|s| s.name == name).unwrap().count;
+        assert_eq!(count("Sampled"), 10);
+        assert_eq!(count("Child"), 100);
+    }
+}
@@ -0,0 +1,113 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Rate at which a scope is sampled
+///
+/// A sampled scope is collected only in 1 of N executions and the
+/// collected scope is marked with "weight" arg equal to N, so the
+/// totals can be scaled back up. A number converts into
+/// `Sampling::Every`.
+///
+/// See [scope!] for sampling the scopes.
+///
+/// [scope!]: macro.scope.html
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, ChromeTracing, Sampling};
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///
+///     for i in 0..100_000 {
+///         // Collects executions 0, 1000, 2000, ...
+///         scope!(every | sample = 1000, "Every {}", i);
+///         // Collects about 100 random executions
+///         scope!(random | sample = Sampling::Random(1000), "Random {}", i);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Collect every Nth execution, starting with the first
+    Every(u32),
+    /// Collect every execution with probability 1/N
+    Random(u32),
+}
+
+impl From<u32> for Sampling {
+    fn from(rate: u32) -> Sampling {
+        Sampling::Every(rate)
+    }
+}
+
+thread_local! {
+    // state of the random number generator of the thread
+    static RANDOM: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    // RandomState is seeded differently for every thread
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish() | 1
+}
+
+// xorshift64*
+fn random() -> u64 {
+    RANDOM.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+// Place in the code where a sampled scope is produced,
+// counts the executions of the scope
+#[doc(hidden)]
+pub struct SampleSite {
+    executions: AtomicU32,
+}
+
+impl Default for SampleSite {
+    fn default() -> SampleSite {
+        SampleSite::new()
+    }
+}
+
+impl SampleSite {
+    pub const fn new() -> SampleSite {
+        SampleSite {
+            executions: AtomicU32::new(0),
+        }
+    }
+
+    // returns the weight if this execution is collected
+    #[inline]
+    pub fn sample(&'static self, sampling: impl Into<Sampling>) -> Option<u32> {
+        match sampling.into() {
+            Sampling::Every(rate) if rate > 1 => {
+                let execution = self.executions.fetch_add(1, Ordering::Relaxed);
+                if execution % rate == 0 {
+                    Some(rate)
+                } else {
+                    None
+                }
+            }
+            Sampling::Random(rate) if rate > 1 => {
+                if random() % rate as u64 == 0 {
+                    Some(rate)
+                } else {
+                    None
+                }
+            }
+            _ => Some(1),
+        }
+    }
+}
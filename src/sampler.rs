@@ -0,0 +1,33 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Background thread that calls a function periodically
+// until it is stopped
+pub struct Sampler {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Sampler {
+    pub fn spawn<F>(interval: Duration, mut sample: F) -> Sampler
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            // the channel disconnects when the sampler is stopped
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                sample();
+            }
+        });
+
+        Sampler { stop, handle }
+    }
+
+    pub fn stop(self) {
+        drop(self.stop);
+        self.handle.join().unwrap();
+    }
+}
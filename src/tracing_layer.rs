@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::bench::{log_with_args, TimeScope};
+use crate::filter;
+use crate::global::{instantiated, Arg};
+
+// fields of a span or an event as args
+struct Fields {
+    message: Option<String>,
+    args: Vec<(String, Arg)>,
+}
+
+impl Fields {
+    fn new() -> Fields {
+        Fields {
+            message: None,
+            args: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, field: &Field, value: Arg) {
+        self.args.push((String::from(field.name()), value));
+    }
+}
+
+impl Visit for Fields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, Arg::Int(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, Arg::Int(value as i64));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, Arg::Float(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, Arg::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, Arg::Str(String::from(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            self.push(field, Arg::Str(format!("{:?}", value)));
+        }
+    }
+}
+
+thread_local! {
+    // scopes of the spans entered on the thread, a span can be
+    // entered several times before it is exited and on several
+    // threads at once
+    static ENTERED: RefCell<Vec<(Id, TimeScope)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Layer of tracing-subscriber that saves spans and events to the trace
+///
+/// Every time a span is entered and exited a [BenchData::Bench] is
+/// saved with the span name as the name and the span fields as args.
+/// Every event is saved as a [BenchData::Log] with the "message" field
+/// as the log and the other fields, "level" and "target" as args. The
+/// category of both is the target, which is the module path by default.
+///
+/// This struct is available with `tracing` feature.
+///
+/// [BenchData::Bench]: enum.BenchData.html#variant.Bench
+/// [BenchData::Log]: enum.BenchData.html#variant.Log
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, ChromeTracing, TracingLayer};
+/// use tracing_subscriber::prelude::*;
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///     tracing_subscriber::registry().with(TracingLayer).init();
+///
+///     // Both are saved to the same trace
+///     scope!(sc | "Scope");
+///     let span = tracing::info_span!("Span", id = 10);
+///     let _enter = span.enter();
+///     tracing::info!(bytes = 1024, "Read the file");
+/// }
+/// ```
+pub struct TracingLayer;
+
+impl<S> Layer<S> for TracingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::new();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        if !instantiated() || !filter::enabled(metadata.name(), metadata.target()) {
+            return;
+        }
+
+        let mut fields = Fields::new();
+        event.record(&mut fields);
+
+        let mut args = fields.args;
+        args.push((
+            String::from("level"),
+            Arg::Str(metadata.level().to_string()),
+        ));
+        args.push((
+            String::from("target"),
+            Arg::Str(String::from(metadata.target())),
+        ));
+
+        let log = fields
+            .message
+            .unwrap_or_else(|| String::from(metadata.name()));
+        log_with_args(log, String::from(metadata.target()), args);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if !instantiated() {
+            return;
+        }
+
+        if let Some(span) = ctx.span(id) {
+            let metadata = span.metadata();
+            let scope = TimeScope::with_category(metadata.name(), metadata.target());
+            ENTERED.with(|entered| entered.borrow_mut().push((id.clone(), scope)));
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let scope = ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            let index = entered.iter().rposition(|(entered, _)| entered == id)?;
+            Some(entered.remove(index).1)
+        });
+
+        // the fields recorded while the span was entered are included
+        if let (Some(mut scope), Some(span)) = (scope, ctx.span(id)) {
+            if let Some(fields) = span.extensions().get::<Fields>() {
+                for (name, value) in fields.args.iter() {
+                    scope.arg(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
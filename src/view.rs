@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+
+use crate::filter::glob_match;
+use crate::global::{Arg, BenchData};
+
+// name of the event, the log for logs
+fn name(data: &BenchData) -> &str {
+    match data {
+        BenchData::Log { log, .. } => log,
+        BenchData::Bench { name, .. } => name,
+        BenchData::Count { name, .. } => name,
+        BenchData::Meta { name, .. } => name,
+    }
+}
+
+// start and end of the event in microseconds
+fn interval(data: &BenchData) -> Option<(f32, f32)> {
+    match data {
+        BenchData::Log { ts, .. } => Some((*ts, *ts)),
+        BenchData::Bench { ts, dur, .. } => Some((*ts, ts + dur)),
+        BenchData::Count { ts, .. } => Some((*ts, *ts)),
+        BenchData::Meta { .. } => None,
+    }
+}
+
+// sorts the indexes of the events by their start
+fn sort_by_start(data: &[BenchData], indexes: &mut [usize]) {
+    indexes.sort_by(|&a, &b| {
+        let (a, b) = (interval(&data[a]), interval(&data[b]));
+        a.unwrap().0.total_cmp(&b.unwrap().0)
+    });
+}
+
+fn tid(data: &BenchData) -> Option<usize> {
+    match data {
+        BenchData::Log { tid, .. } => Some(*tid),
+        BenchData::Bench { tid, .. } => Some(*tid),
+        BenchData::Count { tid, .. } => Some(*tid),
+        BenchData::Meta { .. } => None,
+    }
+}
+
+/// Indexed queries over the collected data
+///
+/// TraceView is built from the data given to a [Writer], returned by
+/// [snapshot] or read from a trace file, and answers the queries
+/// without going through all the events. The events are returned in
+/// the order of their timestamps.
+///
+/// [Writer]: trait.Writer.html
+/// [snapshot]: fn.snapshot.html
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, snapshot, BenchData, ChromeTracing, TraceView};
+///
+/// fn main() {
+///     instantiate!(ChromeTracing("target/bench"));
+///
+///     {
+///         scope!(frame | "Frame");
+///         for i in 0..3 {
+///             scope!(draw | "Draw {}", i);
+///         }
+///     }
+///
+///     let data = snapshot();
+///     let view = TraceView::new(&data);
+///
+///     let frame = view.named("Frame")[0];
+///     if let BenchData::Bench { id, .. } = frame {
+///         assert_eq!(view.children(*id).len(), 3);
+///     }
+///     assert_eq!(view.matching("Draw *").len(), 3);
+/// }
+/// ```
+pub struct TraceView<'a> {
+    data: &'a [BenchData],
+    // events with timestamps sorted by start
+    by_time: Vec<usize>,
+    // segment tree over by_time with the latest end of the events
+    // under every node, the leaves start at by_time.len() rounded
+    // up to a power of two
+    max_end: Vec<f32>,
+    by_thread: HashMap<usize, Vec<usize>>,
+    by_name: HashMap<&'a str, Vec<usize>>,
+    by_span: HashMap<u64, usize>,
+    children: HashMap<u64, Vec<usize>>,
+}
+
+impl<'a> TraceView<'a> {
+    /// Builds the indexes over `data`
+    pub fn new(data: &'a [BenchData]) -> TraceView<'a> {
+        let mut by_time = (0..data.len())
+            .filter(|&i| interval(&data[i]).is_some())
+            .collect::<Vec<_>>();
+        sort_by_start(data, &mut by_time);
+
+        let leaves = by_time.len().next_power_of_two();
+        let mut max_end = vec![f32::NEG_INFINITY; 2 * leaves];
+        for (i, &event) in by_time.iter().enumerate() {
+            max_end[leaves + i] = interval(&data[event]).unwrap().1;
+        }
+        for node in (1..leaves).rev() {
+            max_end[node] = max_end[2 * node].max(max_end[2 * node + 1]);
+        }
+
+        let mut view = TraceView {
+            data,
+            by_time,
+            max_end,
+            by_thread: HashMap::new(),
+            by_name: HashMap::new(),
+            by_span: HashMap::new(),
+            children: HashMap::new(),
+        };
+
+        for &i in view.by_time.iter() {
+            let event = &data[i];
+
+            if let Some(tid) = tid(event) {
+                view.by_thread.entry(tid).or_default().push(i);
+            }
+            view.by_name.entry(name(event)).or_default().push(i);
+
+            if let BenchData::Bench { id, parent, .. } = event {
+                view.by_span.insert(*id, i);
+                if let Some(parent) = parent {
+                    view.children.entry(*parent).or_default().push(i);
+                }
+            }
+        }
+
+        view
+    }
+
+    fn events(&self, indexes: &[usize]) -> Vec<&'a BenchData> {
+        indexes.iter().map(|&i| &self.data[i]).collect()
+    }
+
+    /// Returns the ids of the threads that have events
+    pub fn threads(&self) -> Vec<usize> {
+        let mut threads = self.by_thread.keys().copied().collect::<Vec<_>>();
+        threads.sort_unstable();
+        threads
+    }
+
+    /// Returns the events of thread `tid`
+    pub fn thread(&self, tid: usize) -> Vec<&'a BenchData> {
+        match self.by_thread.get(&tid) {
+            Some(indexes) => self.events(indexes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the events named `name`
+    ///
+    /// The name of a log is its text.
+    pub fn named(&self, name: &str) -> Vec<&'a BenchData> {
+        match self.by_name.get(name) {
+            Some(indexes) => self.events(indexes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the events with names that match a glob `pattern`
+    ///
+    /// The pattern has `*` and `?` wildcards like the patterns
+    /// of [Filter].
+    ///
+    /// [Filter]: struct.Filter.html
+    pub fn matching(&self, pattern: &str) -> Vec<&'a BenchData> {
+        let mut indexes = self
+            .by_name
+            .iter()
+            .filter(|(name, _)| glob_match(pattern, name))
+            .flat_map(|(_, indexes)| indexes.iter().copied())
+            .collect::<Vec<_>>();
+        sort_by_start(self.data, &mut indexes);
+        self.events(&indexes)
+    }
+
+    /// Returns the events that overlap the time range from
+    /// `start` to `end` in microseconds
+    pub fn in_range(&self, start: f32, end: f32) -> Vec<&'a BenchData> {
+        // only the events that start before the end can overlap
+        let last = self.count_starts(|ts| ts <= end);
+        let leaves = self.max_end.len() / 2;
+
+        // nodes of the tree with the ranges of by_time under them,
+        // the left nodes are visited first to keep the order
+        let mut nodes = vec![(1, 0, leaves)];
+        let mut indexes = Vec::new();
+
+        while let Some((node, from, to)) = nodes.pop() {
+            if from >= last || self.max_end[node] < start {
+                continue;
+            }
+
+            if node >= leaves {
+                indexes.push(self.by_time[from]);
+            } else {
+                let middle = (from + to) / 2;
+                nodes.push((2 * node + 1, middle, to));
+                nodes.push((2 * node, from, middle));
+            }
+        }
+
+        self.events(&indexes)
+    }
+
+    /// Returns the scopes that are running at `ts`
+    ///
+    /// The scopes are returned from the outermost.
+    pub fn at(&self, ts: f32) -> Vec<&'a BenchData> {
+        let mut scopes = self
+            .in_range(ts, ts)
+            .into_iter()
+            .filter(|data| matches!(data, BenchData::Bench { .. }))
+            .collect::<Vec<_>>();
+        scopes.sort_by_key(|data| match data {
+            BenchData::Bench { depth, .. } => *depth,
+            _ => 0,
+        });
+        scopes
+    }
+
+    /// Returns the scope with span id `id`
+    pub fn span(&self, id: u64) -> Option<&'a BenchData> {
+        self.by_span.get(&id).map(|&i| &self.data[i])
+    }
+
+    /// Returns the scopes nested directly in the scope with span id `id`
+    pub fn children(&self, id: u64) -> Vec<&'a BenchData> {
+        match self.children.get(&id) {
+            Some(indexes) => self.events(indexes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the value of the [BenchData::Meta] named `name`
+    ///
+    /// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+    pub fn meta(&self, name: &str) -> Option<&'a Arg> {
+        self.data.iter().find_map(|data| match data {
+            BenchData::Meta { name: n, value } if n == name => Some(value),
+            _ => None,
+        })
+    }
+
+    // number of the first events whose starts satisfy `pred`
+    fn count_starts(&self, pred: impl Fn(f32) -> bool) -> usize {
+        self.by_time
+            .partition_point(|&i| pred(interval(&self.data[i]).unwrap().0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn scope(id: u64, ts: f32, dur: f32, parent: Option<u64>, depth: usize) -> BenchData {
+        BenchData::Bench {
+            name: Cow::Owned(format!("Scope {}", id)),
+            cat: Cow::Borrowed("function"),
+            ts,
+            dur,
+            tid: 0,
+            args: Vec::new(),
+            id,
+            parent,
+            depth,
+        }
+    }
+
+    fn ids(events: Vec<&BenchData>) -> Vec<u64> {
+        events
+            .into_iter()
+            .map(|data| match data {
+                BenchData::Bench { id, .. } => *id,
+                data => panic!("unexpected {:?}", data),
+            })
+            .collect()
+    }
+
+    // scopes are saved when they end, so the parents come after
+    // their children: 0 is [0, 10], 1 is [1, 4], 2 is [2, 3],
+    // 3 is [5, 9] and 4 is [20, 25]
+    fn trace() -> Vec<BenchData> {
+        vec![
+            scope(2, 2.0, 1.0, Some(1), 2),
+            scope(1, 1.0, 3.0, Some(0), 1),
+            scope(3, 5.0, 4.0, Some(0), 1),
+            scope(0, 0.0, 10.0, None, 0),
+            scope(4, 20.0, 5.0, None, 0),
+        ]
+    }
+
+    #[test]
+    fn empty() {
+        let view = TraceView::new(&[]);
+        assert!(view.in_range(f32::NEG_INFINITY, f32::INFINITY).is_empty());
+        assert!(view.at(0.0).is_empty());
+    }
+
+    #[test]
+    fn single_event() {
+        let data = [scope(0, 1.0, 2.0, None, 0)];
+        let view = TraceView::new(&data);
+
+        assert_eq!(ids(view.in_range(0.0, 5.0)), [0]);
+        assert_eq!(ids(view.in_range(1.5, 1.5)), [0]);
+        assert!(view.in_range(0.0, 0.5).is_empty());
+        assert!(view.in_range(3.5, 5.0).is_empty());
+        assert_eq!(ids(view.at(2.0)), [0]);
+        assert!(view.at(4.0).is_empty());
+    }
+
+    #[test]
+    fn overlapping_ranges() {
+        let data = trace();
+        let view = TraceView::new(&data);
+
+        assert_eq!(ids(view.in_range(2.5, 6.0)), [0, 1, 2, 3]);
+        assert_eq!(ids(view.in_range(4.5, 4.8)), [0]);
+        assert_eq!(ids(view.in_range(9.5, 21.0)), [0, 4]);
+        assert!(view.in_range(11.0, 19.0).is_empty());
+    }
+
+    #[test]
+    fn out_of_order_events() {
+        let data = trace();
+        let view = TraceView::new(&data);
+
+        // events are returned by their starts, not in the order
+        // they were saved
+        assert_eq!(ids(view.in_range(0.0, 30.0)), [0, 1, 2, 3, 4]);
+        // scopes are returned from the outermost
+        assert_eq!(ids(view.at(2.5)), [0, 1, 2]);
+        assert_eq!(ids(view.at(6.0)), [0, 3]);
+    }
+
+    #[test]
+    fn range_boundaries() {
+        let data = trace();
+        let view = TraceView::new(&data);
+
+        // the events that end at the start or start at the end overlap
+        assert_eq!(ids(view.in_range(10.0, 20.0)), [0, 4]);
+        assert_eq!(ids(view.in_range(4.0, 5.0)), [0, 1, 3]);
+        assert_eq!(ids(view.at(3.0)), [0, 1, 2]);
+        assert_eq!(ids(view.at(25.0)), [4]);
+        assert!(view.at(25.5).is_empty());
+    }
+
+    #[test]
+    fn same_as_scan() {
+        // pseudo random scopes that overlap in any way
+        let mut state = 1u64;
+        let mut random = |max: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % max
+        };
+        let data = (0..100)
+            .map(|id| scope(id, random(1000) as f32, random(100) as f32, None, 0))
+            .collect::<Vec<_>>();
+        let view = TraceView::new(&data);
+
+        for _ in 0..100 {
+            let start = random(1100) as f32;
+            let end = start + random(50) as f32;
+
+            let found = view.in_range(start, end);
+            let starts = found.iter().map(|data| interval(data).unwrap().0);
+            assert!(starts.clone().zip(starts.skip(1)).all(|(a, b)| a <= b));
+
+            let expected = data.iter().filter(|data| {
+                let (ts, te) = interval(data).unwrap();
+                ts <= end && te >= start
+            });
+            // events with the same start can come in any order
+            let mut found = ids(found);
+            let mut expected = ids(expected.collect());
+            found.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -1,9 +1,15 @@
+use std::borrow::Borrow;
 use std::collections::HashSet;
-use std::fs::File;
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::io::Write;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
-use crate::global::BenchData;
+use crate::global::{Arg, BenchData};
+use crate::panic::in_hook;
 
 /// The trait that is implemented by all the writers
 ///
@@ -12,104 +18,737 @@ use crate::global::BenchData;
 /// QueueLogger will log out to console all the collected data
 /// when program ends.
 /// ```rust
+/// use gbench::{BenchData, Writer};
+///
 /// struct QueueLogger;
 /// 
 /// impl Writer for QueueLogger {
-///     fn end(&self, data: &Vec<BenchData>) {
+///     fn end(&self, data: &[BenchData]) {
 ///         for data in data.iter() {
 ///             println!("{:#?}", data);
 ///         }
 ///     }
 /// }
 /// ```
-pub trait Writer {
+pub trait Writer: Send {
     /// This method is called on all the collected data when
-    /// program ends
-    fn end(&self, data: &Vec<BenchData>);
+    /// program ends or when the data is dumped with [dump]
+    ///
+    /// [dump]: fn.dump.html
+    fn end(&self, data: &[BenchData]);
+
+    /// Makes a writer that gives this writer only the events
+    /// for which `predicate` returns true
+    fn filter<P>(self, predicate: P) -> Filtered<Self, P>
+    where
+        Self: Sized,
+        P: Fn(&BenchData) -> bool + Send,
+    {
+        Filtered(self, predicate)
+    }
+
+    /// Makes a writer that gives this writer the events
+    /// transformed by `f`
+    fn map<F>(self, f: F) -> Mapped<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&BenchData) -> BenchData + Send,
+    {
+        Mapped(self, f)
+    }
+}
+
+/// Closures that take the collected data are writers
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, log, BenchData};
+///
+/// fn main() {
+///     instantiate!(|data: &[BenchData]| println!("{} events", data.len()));
+///
+///     log!("Event");
+/// }
+/// ```
+impl<F> Writer for F
+where
+    F: Fn(&[BenchData]) + Send,
+{
+    fn end(&self, data: &[BenchData]) {
+        self(data);
+    }
+}
+
+/// Writer made by [Writer::filter]
+///
+/// [Writer::filter]: trait.Writer.html#method.filter
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{count, instantiate, scope, BenchData, ChromeTracing, CsvWriter, Writer};
+///
+/// fn main() {
+///     instantiate!(
+///         // Only the scopes of thread 0
+///         ChromeTracing("target/bench").filter(|data| match data {
+///             BenchData::Bench { tid, .. } => *tid == 0,
+///             _ => false,
+///         }),
+///         // Only the counters
+///         CsvWriter("target/bench").filter(|data| matches!(data, BenchData::Count { .. }))
+///     );
+///
+///     for i in 0..10 {
+///         scope!(sc | "Scope {}", i);
+///         count!("I" => { "i" => i });
+///     }
+/// }
+/// ```
+pub struct Filtered<W, P>(W, P);
+
+impl<W, P> Writer for Filtered<W, P>
+where
+    W: Writer,
+    P: Fn(&BenchData) -> bool + Send,
+{
+    fn end(&self, data: &[BenchData]) {
+        let data = data.iter().filter(|data| (self.1)(data)).cloned().collect::<Vec<_>>();
+        self.0.end(&data);
+    }
+}
+
+/// Writer made by [Writer::map]
+///
+/// [Writer::map]: trait.Writer.html#method.map
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, scope, BenchData, ChromeTracing, Writer};
+///
+/// fn main() {
+///     // All the scopes are shown on one thread
+///     instantiate!(ChromeTracing("target/bench").map(|data| {
+///         let mut data = data.clone();
+///         if let BenchData::Bench { tid, .. } = &mut data {
+///             *tid = 0;
+///         }
+///         data
+///     }));
+///
+///     let thread = std::thread::spawn(|| {
+///         scope!(sc | "Scope");
+///     });
+///     thread.join().unwrap();
+/// }
+/// ```
+pub struct Mapped<W, F>(W, F);
+
+impl<W, F> Writer for Mapped<W, F>
+where
+    W: Writer,
+    F: Fn(&BenchData) -> BenchData + Send,
+{
+    fn end(&self, data: &[BenchData]) {
+        let data = data.iter().map(|data| (self.1)(data)).collect::<Vec<_>>();
+        self.0.end(&data);
+    }
+}
+
+/// Writer that gives the data to both of the writers
+///
+/// # Examples
+///
+/// ```rust
+/// use gbench::{instantiate, log, BenchData, ChromeTracing, Tee};
+///
+/// fn main() {
+///     instantiate!(Tee(
+///         ChromeTracing("target/bench"),
+///         |data: &[BenchData]| println!("{} events", data.len()),
+///     ));
+///
+///     log!("Event");
+/// }
+/// ```
+pub struct Tee<A, B>(pub A, pub B);
+
+impl<A: Writer, B: Writer> Writer for Tee<A, B> {
+    fn end(&self, data: &[BenchData]) {
+        self.0.end(data);
+        self.1.end(data);
+    }
+}
+
+/// Information about the collection given to [StreamWriter::begin]
+///
+/// [StreamWriter::begin]: trait.StreamWriter.html#tymethod.begin
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    /// Moment the collection started
+    pub start: SystemTime,
+    /// Values of [BenchData::Meta] such as "scope overhead"
+    ///
+    /// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+    pub meta: Vec<(String, Arg)>,
+}
+
+/// The trait of the writers that receive the events as they happen
+///
+/// Unlike [Writer], which is given all the collected data at the end,
+/// a stream writer starts the output in [begin], receives every event
+/// in [event] when it is collected and completes the output in
+/// [finish] when the collection ends. The state of the output is kept
+/// in the session returned by [begin]. The stream writers receive
+/// all the events even in flight recorder mode.
+///
+/// Every stream writer runs on its own thread, so a slow output does
+/// not hold the other threads, unless the writer is thousands of
+/// events behind. A writer that panics, for example on an I/O error,
+/// is not given any more events and its output is not finished.
+///
+/// A stream writer is added with [Collector::stream] or
+/// [Instantiator::stream]. Any [Writer] can be added as a stream
+/// writer by wrapping it in [Batch].
+///
+/// [Writer]: trait.Writer.html
+/// [begin]: trait.StreamWriter.html#tymethod.begin
+/// [event]: trait.StreamWriter.html#tymethod.event
+/// [finish]: trait.StreamWriter.html#tymethod.finish
+/// [Collector::stream]: struct.Collector.html#method.stream
+/// [Instantiator::stream]: struct.Instantiator.html#method.stream
+/// [Batch]: struct.Batch.html
+///
+/// # Examples
+///
+/// EventPrinter prints the events as soon as they are collected.
+/// ```rust
+/// use gbench::{instantiate, log, BenchData, SessionInfo, StreamWriter};
+///
+/// struct EventPrinter;
+///
+/// impl StreamWriter for EventPrinter {
+///     // number of the printed events
+///     type Session = usize;
+///
+///     fn begin(&self, info: &SessionInfo) -> usize {
+///         println!("Started at {:?}", info.start);
+///         0
+///     }
+///
+///     fn event(&self, printed: &mut usize, data: &BenchData) {
+///         println!("{:?}", data);
+///         *printed += 1;
+///     }
+///
+///     fn finish(&self, printed: usize) {
+///         println!("Printed {} events", printed);
+///     }
+/// }
+///
+/// fn main() {
+///     instantiate!(ginst | );
+///     ginst.stream(EventPrinter);
+///
+///     log!("Printed right away");
+/// }
+/// ```
+pub trait StreamWriter: Send + 'static {
+    /// State of the output during the collection
+    type Session: Send;
+
+    /// This method is called when the writer is added
+    fn begin(&self, info: &SessionInfo) -> Self::Session;
+
+    /// This method is called on every collected event
+    fn event(&self, session: &mut Self::Session, data: &BenchData);
+
+    /// This method is called when the collection ends
+    fn finish(&self, session: Self::Session);
+}
+
+/// Adapter that adds a [Writer] as a [StreamWriter]
+///
+/// The events are kept until the collection ends and then
+/// given to the writer.
+///
+/// [Writer]: trait.Writer.html
+/// [StreamWriter]: trait.StreamWriter.html
+pub struct Batch<W: Writer>(pub W);
+
+impl<W: Writer + 'static> StreamWriter for Batch<W> {
+    type Session = Vec<BenchData>;
+
+    fn begin(&self, info: &SessionInfo) -> Vec<BenchData> {
+        info.meta.iter().map(|(name, value)| BenchData::Meta {
+            name: name.clone(),
+            value: value.clone(),
+        }).collect()
+    }
+
+    fn event(&self, session: &mut Vec<BenchData>, data: &BenchData) {
+        session.push(data.clone());
+    }
+
+    fn finish(&self, session: Vec<BenchData>) {
+        self.0.end(&session);
+    }
+}
+
+// number of events a stream can be behind the collection
+const STREAM_BUFFER: usize = 4096;
+
+// collected event with its sequence number
+pub(crate) type Event = (u64, Arc<BenchData>);
+
+// stream that runs on its own thread, so the events
+// are written outside of the collector locks
+pub(crate) struct Stream {
+    events: SyncSender<Event>,
+    thread: JoinHandle<()>,
+}
+
+impl Stream {
+    pub fn spawn(run: impl FnOnce(Receiver<Event>) + Send + 'static) -> Stream {
+        let (events, receiver) = mpsc::sync_channel(STREAM_BUFFER);
+        let thread = thread::spawn(move || run(receiver));
+        Stream { events, thread }
+    }
+
+    pub fn begin<W: StreamWriter>(writer: W, info: SessionInfo) -> Stream {
+        Stream::spawn(move |events| run_stream(writer, info, events))
+    }
+
+    // the collection waits for the streams that are behind,
+    // except in the panic hook
+    pub fn event(&self, seq: u64, data: &Arc<BenchData>) {
+        if in_hook() {
+            let _ = self.events.try_send((seq, data.clone()));
+        } else {
+            let _ = self.events.send((seq, data.clone()));
+        }
+    }
+
+    // waits until the stream has written all the events, unless
+    // it is finished by the panic hook on its own thread
+    pub fn finish(self) {
+        drop(self.events);
+        if self.thread.thread().id() != thread::current().id() {
+            let _ = self.thread.join();
+        }
+    }
+}
+
+// the stream is disabled if the writer panics, for example on an
+// I/O error, as the receiver is dropped with the thread and the
+// events are no longer sent to it
+fn run_stream<W: StreamWriter>(writer: W, info: SessionInfo, events: Receiver<Event>) {
+    let mut session = writer.begin(&info);
+    for (_, data) in events {
+        writer.event(&mut session, &data);
+    }
+    writer.finish(session);
+}
+
+// creates a new file in the folder, creating the folder if needed
+fn create_file(folder: &str, prefix: &str, extension: &str) -> File {
+    fs::create_dir_all(folder).unwrap();
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    // the data can be written several times in a millisecond
+    // when it is dumped
+    let mut path = format!("{}/{}-{}.{}", folder, prefix, millis, extension);
+    let mut copy = 0;
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                copy += 1;
+                path = format!("{}/{}-{}-{}.{}", folder, prefix, millis, copy, extension);
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+/// Compression of the output files
+///
+/// Used with [Compressed]. The files are compressed while they are
+/// written, so the whole file is never kept in memory.
+///
+/// This enum is available with `gzip` or `zstd` feature.
+///
+/// [Compressed]: struct.Compressed.html
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, the files get `.gz` extension
+    ///
+    /// Available with `gzip` feature. Perfetto and chrome://tracing
+    /// open `.json.gz` files directly.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, scope, ChromeTracing, Compressed, Compression};
+    ///
+    /// fn main() {
+    ///     // Saves target/bench/bench-*.json.gz
+    ///     instantiate!(Compressed(ChromeTracing("target/bench"), Compression::Gzip));
+    ///
+    ///     scope!(sc | "Scope");
+    /// }
+    /// ```
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// zstd, the files get `.zst` extension
+    ///
+    /// Available with `zstd` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gbench::{instantiate, scope, ChromeTracing, Compressed, Compression};
+    ///
+    /// fn main() {
+    ///     instantiate!(ginst | );
+    ///     // Saves target/bench/bench-*.json.zst as the events are collected
+    ///     ginst.stream(Compressed(ChromeTracing("target/bench"), Compression::Zstd));
+    ///
+    ///     scope!(sc | "Scope");
+    /// }
+    /// ```
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+#[cfg(not(any(feature = "gzip", feature = "zstd")))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Compression {}
+
+/// Writer that compresses the files of [ChromeTracing] or [CsvWriter]
+///
+/// Works both as a [Writer] and as a [StreamWriter]. See [Compression]
+/// for the examples.
+///
+/// The other writers are not supported. Browsers do not open compressed
+/// `.html` and `.svg` files from the disk, so HtmlReportWriter and
+/// FlameChartWriter write plain files, and the binary format is already
+/// compact and is read by `gbench convert` as it is.
+///
+/// This struct is available with `gzip` or `zstd` feature.
+///
+/// [ChromeTracing]: struct.ChromeTracing.html
+/// [CsvWriter]: struct.CsvWriter.html
+/// [Writer]: trait.Writer.html
+/// [StreamWriter]: trait.StreamWriter.html
+/// [Compression]: enum.Compression.html
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub struct Compressed<W>(pub W, pub Compression);
+
+// output file that is compressed while it is written
+pub(crate) enum Output {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Output {
+    pub(crate) fn create(folder: &str, prefix: &str, extension: &str, compression: Option<Compression>) -> Output {
+        match compression {
+            None => Output::Plain(BufWriter::new(create_file(folder, prefix, extension))),
+            Some(compression) => match compression {
+                #[cfg(feature = "gzip")]
+                Compression::Gzip => {
+                    let file = create_file(folder, prefix, &format!("{}.gz", extension));
+                    let level = flate2::Compression::default();
+                    Output::Gzip(flate2::write::GzEncoder::new(BufWriter::new(file), level))
+                }
+                #[cfg(feature = "zstd")]
+                Compression::Zstd => {
+                    let file = create_file(folder, prefix, &format!("{}.zst", extension));
+                    Output::Zstd(zstd::Encoder::new(BufWriter::new(file), 0).unwrap())
+                }
+            },
+        }
+    }
+
+    // writes the end of the compressed stream
+    #[allow(clippy::infallible_destructuring_match)] // the variants depend on the features
+    pub(crate) fn finish(self) {
+        let mut file = match self {
+            Output::Plain(file) => file,
+            #[cfg(feature = "gzip")]
+            Output::Gzip(encoder) => encoder.finish().unwrap(),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.finish().unwrap(),
+        };
+        file.flush().unwrap();
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            #[cfg(feature = "gzip")]
+            Output::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            #[cfg(feature = "gzip")]
+            Output::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
 }
 
 /// Writer for google chrome tracing
 ///
 /// First field is the name of the folder where the 
-/// output files will be saved.
+/// output files will be saved. The folder is created
+/// if it does not exist.
 pub struct ChromeTracing(pub &'static str);
 
-fn write_data(file: &mut File, data: &BenchData) {
+pub(crate) fn write_str(file: &mut impl Write, s: &str) {
+    write!(file, "\"").unwrap();
+    for c in s.chars() {
+        match c {
+            '"' => write!(file, "\\\"").unwrap(),
+            '\\' => write!(file, "\\\\").unwrap(),
+            '\n' => write!(file, "\\n").unwrap(),
+            '\r' => write!(file, "\\r").unwrap(),
+            '\t' => write!(file, "\\t").unwrap(),
+            c if (c as u32) < 0x20 => write!(file, "\\u{:04x}", c as u32).unwrap(),
+            c => write!(file, "{}", c).unwrap(),
+        }
+    }
+    write!(file, "\"").unwrap();
+}
+
+// json has no numbers for NaN and infinities, so they are written
+// as null. Floats always have a fraction or an exponent
+pub(crate) fn write_float<F: Into<f64> + Debug + Copy>(file: &mut impl Write, v: F) {
+    if v.into().is_finite() {
+        write!(file, "{:?}", v).unwrap();
+    } else {
+        write!(file, "null").unwrap();
+    }
+}
+
+pub(crate) fn write_arg(file: &mut impl Write, arg: &Arg) {
+    match arg {
+        Arg::Int(v) => write!(file, "{}", v).unwrap(),
+        Arg::Float(v) => write_float(file, *v),
+        Arg::Str(v) => write_str(file, v),
+        Arg::Bool(v) => write!(file, "{}", v).unwrap(),
+    }
+}
+
+pub(crate) fn write_args(file: &mut impl Write, args: &[(String, Arg)]) {
+    write!(file, "{{").unwrap();
+
+    for (i, (name, value)) in args.iter().enumerate() {
+        if i != 0 {
+            write!(file, ",").unwrap();
+        }
+
+        write_str(file, name);
+        write!(file, ":").unwrap();
+        write_arg(file, value);
+    }
+
+    write!(file, "}}").unwrap();
+}
+
+fn write_data(file: &mut impl Write, data: &BenchData) {
     match data {
-        BenchData::Log { log, ts, tid } => write!(
-            file,
-            "{{\"cat\":\"log\",\"name\":\"{}\",\"ph\":\"I\",\"pid\":0,\"tid\":{},\"ts\":{}}}",
-            log, tid, ts
-        )
-        .unwrap(),
-        BenchData::Bench { name, ts, dur, tid } => write!(
-            file,
-            "{{\"cat\":\"function\",\"dur\":{},\"name\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{}}}", 
-            dur, name, tid,  ts
-        ).unwrap(),
-        BenchData::Count {name, ts, tid, data} => {
-            write!(
-                file, 
-                "{{\"cat\":\"count\",\"name\":\"{}\",\"ph\":\"C\",\"pid\":0,\"tid\":{},\"ts\":{}, \"args\":{{", 
-                name, tid, ts
-            ).unwrap();
-
-            let mut dataiter = data.into_iter();
-
-            if let Some((name, value)) = dataiter.next() {
-                write!(
-                    file,
-                    "\"{}\":{}",
-                    name, value
-                ).unwrap();
+        BenchData::Log { log, cat, ts, tid, args } => {
+            write!(file, "{{\"cat\":").unwrap();
+            write_str(file, cat);
+            write!(file, ",\"name\":").unwrap();
+            write_str(file, log);
+            write!(file, ",\"ph\":\"I\",\"pid\":0,\"tid\":{},\"ts\":{}", tid, ts).unwrap();
+
+            if !args.is_empty() {
+                write!(file, ",\"args\":").unwrap();
+                write_args(file, args);
+            }
+
+            write!(file, "}}").unwrap();
+        }
+        BenchData::Bench { name, cat, ts, dur, tid, args, .. } => {
+            write!(file, "{{\"cat\":").unwrap();
+            write_str(file, cat);
+            write!(file, ",\"dur\":{},\"name\":", dur).unwrap();
+            write_str(file, name);
+            write!(file, ",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{}", tid, ts).unwrap();
+
+            if !args.is_empty() {
+                write!(file, ",\"args\":").unwrap();
+                write_args(file, args);
             }
 
-            for (name, value) in dataiter {
-                write!(
-                    file, 
-                    ",\"{}\":{}",
-                    name, value
-                ).unwrap();
+            write!(file, "}}").unwrap();
+        }
+        BenchData::Count {name, cat, ts, tid, data} => {
+            write!(file, "{{\"cat\":").unwrap();
+            write_str(file, cat);
+            write!(file, ",\"name\":").unwrap();
+            write_str(file, name);
+            write!(file, ",\"ph\":\"C\",\"pid\":0,\"tid\":{},\"ts\":{}, \"args\":{{", tid, ts).unwrap();
+
+            for (i, (name, value)) in data.iter().enumerate() {
+                if i != 0 {
+                    write!(file, ",").unwrap();
+                }
+
+                write_str(file, name);
+                write!(file, ":").unwrap();
+                write_float(file, *value);
             }
 
-            write!(
-                file, 
-                "}}}}"
-            ).unwrap();
+            write!(file, "}}}}").unwrap();
         }
+        BenchData::Meta { .. } => {}
     }
 }
 
-impl Writer for ChromeTracing {
-    fn end(&self, data: &Vec<BenchData>) {
-        // write data to file
-        let folder = self.0;
-        let mut file = File::create(format!(
-            "{}/bench-{}.json",
-            folder,
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-        ))
-        .unwrap();
-
-        write!(file, "{{\"otherData\":{{}},\"traceEvents\":[").unwrap();
+fn write_header(file: &mut impl Write, meta: &[(String, Arg)]) {
+    write!(file, "{{\"otherData\":").unwrap();
+    write_args(file, meta);
+    write!(file, ",\"traceEvents\":[").unwrap();
+}
 
-        let mut data = data.iter();
+impl ChromeTracing {
+    /// Writes the data in chrome tracing format to `out`
+    ///
+    /// The events are written as they are taken from `data`, so
+    /// it can be an iterator over a trace that does not fit in
+    /// memory. The [BenchData::Meta] are written after the events.
+    ///
+    /// [BenchData::Meta]: enum.BenchData.html#variant.Meta
+    pub fn write_to<D: Borrow<BenchData>>(out: &mut impl Write, data: impl IntoIterator<Item = D>) {
+        write!(out, "{{\"traceEvents\":[").unwrap();
 
-        // body
-        if let Some(data) = data.next() {
-            write_data(&mut file, data);
-        }
+        // metadata goes to otherData
+        let mut meta = Vec::new();
+        let mut written = false;
 
         for data in data {
-            write!(file, ",").unwrap();
-            write_data(&mut file, data);
+            match data.borrow() {
+                BenchData::Meta { name, value } => meta.push((name.clone(), value.clone())),
+                data => {
+                    if written {
+                        write!(out, ",").unwrap();
+                    }
+                    write_data(out, data);
+                    written = true;
+                }
+            }
         }
 
         // write footer
-        write!(file, "]}}").unwrap();
+        write!(out, "],\"otherData\":").unwrap();
+        write_args(out, &meta);
+        write!(out, "}}").unwrap();
+    }
+}
+
+fn write_chrome(folder: &str, compression: Option<Compression>, data: &[BenchData]) {
+    // write data to file
+    let mut file = Output::create(folder, "bench", "json", compression);
+    ChromeTracing::write_to(&mut file, data);
+    file.finish();
+}
+
+// Session of ChromeTracing stream writer
+pub struct ChromeSession {
+    file: Output,
+    // whether an event was written
+    written: bool,
+}
+
+fn begin_chrome(folder: &str, compression: Option<Compression>, info: &SessionInfo) -> ChromeSession {
+    let mut file = Output::create(folder, "bench", "json", compression);
+    write_header(&mut file, &info.meta);
+    ChromeSession { file, written: false }
+}
+
+fn chrome_event(session: &mut ChromeSession, data: &BenchData) {
+    if let BenchData::Meta { .. } = data {
+        return;
+    }
+
+    if session.written {
+        write!(session.file, ",").unwrap();
+    }
+    write_data(&mut session.file, data);
+    session.written = true;
+}
+
+fn finish_chrome(mut session: ChromeSession) {
+    write!(session.file, "]}}").unwrap();
+    session.file.finish();
+}
+
+impl Writer for ChromeTracing {
+    fn end(&self, data: &[BenchData]) {
+        write_chrome(self.0, None, data);
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl Writer for Compressed<ChromeTracing> {
+    fn end(&self, data: &[BenchData]) {
+        write_chrome((self.0).0, Some(self.1), data);
+    }
+}
+
+impl StreamWriter for ChromeTracing {
+    type Session = ChromeSession;
+
+    fn begin(&self, info: &SessionInfo) -> ChromeSession {
+        begin_chrome(self.0, None, info)
+    }
+
+    fn event(&self, session: &mut ChromeSession, data: &BenchData) {
+        chrome_event(session, data);
+    }
+
+    fn finish(&self, session: ChromeSession) {
+        finish_chrome(session);
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl StreamWriter for Compressed<ChromeTracing> {
+    type Session = ChromeSession;
+
+    fn begin(&self, info: &SessionInfo) -> ChromeSession {
+        begin_chrome((self.0).0, Some(self.1), info)
+    }
+
+    fn event(&self, session: &mut ChromeSession, data: &BenchData) {
+        chrome_event(session, data);
+    }
+
+    fn finish(&self, session: ChromeSession) {
+        finish_chrome(session);
     }
 }
 
@@ -119,7 +758,8 @@ impl Writer for ChromeTracing {
 /// of a csv table.
 ///
 /// First field is the name of the folder where the 
-/// output files will be saved.
+/// output files will be saved. The folder is created
+/// if it does not exist.
 pub struct CsvWriter(pub &'static str);
 
 const DELIMITER: char = ';';
@@ -128,67 +768,267 @@ fn tstr(v: f32) -> String {
     v.to_string().replace(".", ",")
 }
 
-impl Writer for CsvWriter {
-    fn end(&self, data: &Vec<BenchData>) {
-        let folder= self.0;
-        let mut file = File::create(format!(
-            "{}/graph-{}.csv", 
-            folder,
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis())
-        ).unwrap();
-
-        let (fields, csvdata) = {
-            let mut fields = HashSet::new();
-            let mut csvdata = Vec::new();
-
-            for bdata in data {
-                if let BenchData::Count {name, ts, data, tid: _} = bdata {
-                    for (varname, value) in data {
-                        let fieldname = format!("{} : {}", name, varname);
-                        csvdata.push((fieldname.clone(), *value, *ts));
-                        fields.insert(fieldname);
-                    }
-                }
+// name of the field, value and timestamp of every counter field
+type Counts = Vec<(String, f32, f32)>;
+
+fn push_counts(counts: &mut Counts, data: &BenchData) {
+    if let BenchData::Count {name, ts, data, ..} = data {
+        for (varname, value) in data {
+            counts.push((format!("{} : {}", name, varname), *value, *ts));
+        }
+    }
+}
+
+fn write_csv(folder: &str, compression: Option<Compression>, counts: Counts) {
+    let mut file = Output::create(folder, "graph", "csv", compression);
+
+    let (fields, csvdata) = {
+        let mut fields = counts.iter().map(|(fieldname, _, _)| fieldname.clone()).collect::<HashSet<_>>()
+            .into_iter().collect::<Vec<_>>();
+        fields.sort();
+
+        let csvdata = counts.into_iter().map(|(fieldname, value, ts)| {
+            (fields.binary_search(&fieldname).unwrap(), value, ts)
+        }).collect::<Vec<_>>();
+
+        (fields, csvdata)
+    };
+
+    let fieldcount = fields.len();
+    let rows = csvdata.into_iter().scan(vec![None;fieldcount], |state, (idx, value, ts)| {
+        state[idx] = Some(value);
+        Some((ts, state.clone()))
+    });
+
+    write!(file, "ts").unwrap();
+
+    for field in fields {
+        write!(file, "{}{}", DELIMITER, field).unwrap();
+    }
+
+    writeln!(file).unwrap();
+    
+    for (ts, data) in rows {
+        write!(file, "{}", tstr(ts)).unwrap();
+        
+        for datapart in data {
+            write!(file, "{}", DELIMITER).unwrap();
+            if let Some(data) = datapart {
+                write!(file, "{}", tstr(data)).unwrap();
             }
+        }
+        
+        writeln!(file).unwrap();
+    }
+
+    file.finish();
+}
 
-            let mut fields = fields.into_iter().collect::<Vec<_>>();
-            fields.sort();
+fn counts(data: &[BenchData]) -> Counts {
+    let mut counts = Vec::new();
+    for bdata in data {
+        push_counts(&mut counts, bdata);
+    }
+    counts
+}
 
-            let csvdata = csvdata.into_iter().map(|(fieldname, value, ts)| {
-                (fields.binary_search(&fieldname).unwrap(), value, ts)
-            }).collect::<Vec<_>>();
+impl Writer for CsvWriter {
+    fn end(&self, data: &[BenchData]) {
+        write_csv(self.0, None, counts(data));
+    }
+}
 
-            (fields, csvdata)
-        };
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl Writer for Compressed<CsvWriter> {
+    fn end(&self, data: &[BenchData]) {
+        write_csv((self.0).0, Some(self.1), counts(data));
+    }
+}
+
+// the columns are known when the collection ends,
+// so only the counters are kept until then
+impl StreamWriter for CsvWriter {
+    type Session = Counts;
 
-        let fieldcount = fields.len();
-        let rows = csvdata.into_iter().scan(vec![None;fieldcount], |state, (idx, value, ts)| {
-            state[idx] = Some(value);
-            Some((ts, state.clone()))
-        });
+    fn begin(&self, _info: &SessionInfo) -> Counts {
+        Vec::new()
+    }
+
+    fn event(&self, counts: &mut Counts, data: &BenchData) {
+        push_counts(counts, data);
+    }
+
+    fn finish(&self, counts: Counts) {
+        write_csv(self.0, None, counts);
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl StreamWriter for Compressed<CsvWriter> {
+    type Session = Counts;
+
+    fn begin(&self, _info: &SessionInfo) -> Counts {
+        Vec::new()
+    }
+
+    fn event(&self, counts: &mut Counts, data: &BenchData) {
+        push_counts(counts, data);
+    }
+
+    fn finish(&self, counts: Counts) {
+        write_csv((self.0).0, Some(self.1), counts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn not_finite_is_null() {
+        let data = vec![
+            BenchData::Bench {
+                name: Cow::Borrowed("Scope"),
+                cat: Cow::Borrowed("function"),
+                ts: 0.0,
+                dur: 1.0,
+                tid: 0,
+                args: vec![
+                    (String::from("nan"), Arg::Float(f64::NAN)),
+                    (String::from("inf"), Arg::Float(f64::NEG_INFINITY)),
+                    (String::from("float"), Arg::Float(2.0)),
+                ],
+                id: 0,
+                parent: None,
+                depth: 0,
+            },
+            BenchData::Count {
+                name: String::from("Counter"),
+                cat: String::from("count"),
+                ts: 0.0,
+                tid: 0,
+                data: vec![(String::from("inf"), f32::INFINITY)],
+            },
+            BenchData::Meta {
+                name: String::from("meta"),
+                value: Arg::Float(f64::NAN),
+            },
+        ];
+
+        let mut out = Vec::new();
+        ChromeTracing::write_to(&mut out, &data);
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("\"args\":{\"nan\":null,\"inf\":null,\"float\":2.0}"));
+        assert!(out.contains("\"args\":{\"inf\":null}"));
+        assert!(out.contains("\"otherData\":{\"meta\":null}"));
+    }
 
-        write!(file, "ts").unwrap();
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    mod compressed {
+        use super::*;
+        #[cfg(feature = "gzip")]
+        use std::io::Read;
 
-        for field in fields {
-            write!(file, "{}{}", DELIMITER, field).unwrap();
+        fn sample() -> Vec<BenchData> {
+            vec![
+                BenchData::Meta {
+                    name: String::from("scope overhead"),
+                    value: Arg::Float(0.5),
+                },
+                BenchData::Bench {
+                    name: Cow::Borrowed("Scope"),
+                    cat: Cow::Borrowed("function"),
+                    ts: 0.0,
+                    dur: 2.5,
+                    tid: 0,
+                    args: vec![(String::from("allocs"), Arg::Int(3))],
+                    id: 0,
+                    parent: None,
+                    depth: 0,
+                },
+                BenchData::Count {
+                    name: String::from("Counter"),
+                    cat: String::from("count"),
+                    ts: 1.0,
+                    tid: 0,
+                    data: vec![(String::from("a"), 1.0), (String::from("b"), 2.0)],
+                },
+            ]
         }
 
-        write!(file, "\n").unwrap();
-        
-        for (ts, data) in rows {
-            write!(file, "{}", tstr(ts)).unwrap();
-            
-            for datapart in data {
-                write!(file, "{}", DELIMITER).unwrap();
-                if let Some(data) = datapart {
-                    write!(file, "{}", tstr(data)).unwrap();
-                }
+        // an empty folder for the output of a test
+        fn folder(name: &str) -> &'static str {
+            let folder = format!("target/compressed-test/{}", name);
+            let _ = std::fs::remove_dir_all(&folder);
+            Box::leak(folder.into_boxed_str())
+        }
+
+        // contents of the only file in the folder
+        fn read_output(folder: &str) -> Vec<u8> {
+            let mut files = std::fs::read_dir(folder).unwrap();
+            let file = files.next().unwrap().unwrap();
+            assert!(files.next().is_none());
+            std::fs::read(file.path()).unwrap()
+        }
+
+        fn stream<W: StreamWriter>(writer: &W, data: &[BenchData]) {
+            let info = SessionInfo {
+                start: SystemTime::now(),
+                meta: vec![(String::from("scope overhead"), Arg::Float(0.5))],
+            };
+            let mut session = writer.begin(&info);
+            for data in data {
+                writer.event(&mut session, data);
             }
-            
-            write!(file, "\n").unwrap();
+            writer.finish(session);
+        }
+
+        // writes the sample with every compressed writer and compares the
+        // decompressed files with the files of the uncompressed writers
+        fn round_trip(name: &str, compression: Compression, decompress: fn(&[u8]) -> Vec<u8>) {
+            let data = sample();
+
+            let plain = folder(&format!("{}-chrome", name));
+            let compressed = folder(&format!("{}-chrome-compressed", name));
+            ChromeTracing(plain).end(&data);
+            Compressed(ChromeTracing(compressed), compression).end(&data);
+            assert_eq!(decompress(&read_output(compressed)), read_output(plain));
+
+            let plain = folder(&format!("{}-chrome-stream", name));
+            let compressed = folder(&format!("{}-chrome-stream-compressed", name));
+            stream(&ChromeTracing(plain), &data);
+            stream(&Compressed(ChromeTracing(compressed), compression), &data);
+            assert_eq!(decompress(&read_output(compressed)), read_output(plain));
+
+            let plain = folder(&format!("{}-csv", name));
+            let compressed = folder(&format!("{}-csv-compressed", name));
+            CsvWriter(plain).end(&data);
+            Compressed(CsvWriter(compressed), compression).end(&data);
+            assert_eq!(decompress(&read_output(compressed)), read_output(plain));
+
+            let plain = folder(&format!("{}-csv-stream", name));
+            let compressed = folder(&format!("{}-csv-stream-compressed", name));
+            stream(&CsvWriter(plain), &data);
+            stream(&Compressed(CsvWriter(compressed), compression), &data);
+            assert_eq!(decompress(&read_output(compressed)), read_output(plain));
+        }
+
+        #[cfg(feature = "gzip")]
+        #[test]
+        fn gzip_round_trip() {
+            round_trip("gzip", Compression::Gzip, |bytes| {
+                let mut out = Vec::new();
+                flate2::read::GzDecoder::new(bytes).read_to_end(&mut out).unwrap();
+                out
+            });
+        }
+
+        #[cfg(feature = "zstd")]
+        #[test]
+        fn zstd_round_trip() {
+            round_trip("zstd", Compression::Zstd, |bytes| zstd::decode_all(bytes).unwrap());
         }
     }
-}
\ No newline at end of file
+}